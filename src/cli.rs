@@ -0,0 +1,280 @@
+use crate::config::{Integrator, SimConfig};
+use crate::consts::{HEIGHT, WIDTH};
+use nalgebra::Vector2;
+use std::env;
+use std::str::FromStr;
+
+pub const USAGE: &str = "Usage: gravitation-particles [OPTIONS] [SCENARIO]
+
+Arguments:
+  [SCENARIO]                 .toml scenario or .csv/.json particle file (default: scenarios/default.toml)
+
+Options (physics options override the scenario `[config]` table):
+  --width <PX>               window width (default: 1024)
+  --height <PX>              window height (default: 1024)
+  --world-width <UNITS>      world width (default: 512)
+  --world-height <UNITS>     world height (default: 512)
+  --g <G>                    gravitational constant (default: 0.04)
+  --coulomb <K>              Coulomb constant, only acts on charged particles (default: 1)
+  --softening <UNITS>        gravitational softening length (default: 2.5)
+  --theta <THETA>            Barnes-Hut opening angle (default: 0.5)
+  --dt <DT>                  time step (default: 1)
+  --integrator <NAME>        `euler` or `leapfrog` (default: euler)
+  --seed <SEED>              seed of the random initial conditions (default: random)
+  --particles <N>            particles amount of every generated component in the scenario
+  --record                   start recording right away
+  --log                      write the diagnostics CSV log
+  --headless <STEPS>         run STEPS steps without a window
+  --vtk-every <N>            export a VTK frame every N steps (headless only)
+  --check-solar-system       run the solar system for one year and check the Earth returns to its start
+  -h, --help                 print this message";
+
+pub struct Args {
+    pub window_size: Vector2<f32>,
+    pub world_width: Option<f32>,
+    pub world_height: Option<f32>,
+    pub g: Option<f32>,
+    pub coulomb: Option<f32>,
+    pub softening: Option<f32>,
+    pub theta: Option<f32>,
+    pub dt: Option<f32>,
+    pub integrator: Option<Integrator>,
+    pub seed: Option<u64>,
+    pub scenario: Option<String>,
+    pub particles: Option<i32>,
+    pub record: bool,
+    pub log: bool,
+    pub headless: Option<u32>,
+    pub vtk_every: Option<u32>,
+    pub check_solar_system: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            window_size: Vector2::new(WIDTH, HEIGHT),
+            world_width: None,
+            world_height: None,
+            g: None,
+            coulomb: None,
+            softening: None,
+            theta: None,
+            dt: None,
+            integrator: None,
+            seed: None,
+            scenario: None,
+            particles: None,
+            record: false,
+            log: false,
+            headless: None,
+            vtk_every: None,
+            check_solar_system: false,
+        }
+    }
+}
+
+fn value<T: FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<T, String> {
+    let raw = args
+        .next()
+        .ok_or_else(|| format!("`{}` expects a value", flag))?;
+    raw.parse()
+        .map_err(|_| format!("`{}` is not a valid value for `{}`", raw, flag))
+}
+
+fn positive(value: f32, flag: &str) -> Result<f32, String> {
+    if value > 0.0 && value.is_finite() {
+        Ok(value)
+    } else {
+        Err(format!("`{}` has to be positive, got {}", flag, value))
+    }
+}
+
+impl Args {
+    pub fn parse() -> Self {
+        match Self::parse_from(env::args().skip(1)) {
+            Ok(Some(args)) => args,
+            Ok(None) => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            Err(parsing_err) => {
+                eprintln!("Error: {}\n\n{}", parsing_err, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    // `Ok(None)` means help was requested
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "--width" => parsed.window_size.x = positive(value(&mut args, &arg)?, &arg)?,
+                "--height" => parsed.window_size.y = positive(value(&mut args, &arg)?, &arg)?,
+                "--world-width" => {
+                    parsed.world_width = Some(positive(value(&mut args, &arg)?, &arg)?)
+                }
+                "--world-height" => {
+                    parsed.world_height = Some(positive(value(&mut args, &arg)?, &arg)?)
+                }
+                "--g" => parsed.g = Some(value(&mut args, &arg)?),
+                "--coulomb" => parsed.coulomb = Some(value(&mut args, &arg)?),
+                "--softening" => parsed.softening = Some(value(&mut args, &arg)?),
+                "--theta" => parsed.theta = Some(positive(value(&mut args, &arg)?, &arg)?),
+                "--dt" => parsed.dt = Some(positive(value(&mut args, &arg)?, &arg)?),
+                "--integrator" => {
+                    let name: String = value(&mut args, &arg)?;
+                    parsed.integrator = Some(name.parse()?);
+                }
+                "--seed" => parsed.seed = Some(value(&mut args, &arg)?),
+                "--particles" => {
+                    let amount: i32 = value(&mut args, &arg)?;
+                    positive(amount as f32, &arg)?;
+                    parsed.particles = Some(amount);
+                }
+                "--record" => parsed.record = true,
+                "--log" => parsed.log = true,
+                "--headless" => parsed.headless = Some(value(&mut args, &arg)?),
+                "--vtk-every" => {
+                    let every: u32 = value(&mut args, &arg)?;
+                    positive(every as f32, &arg)?;
+                    parsed.vtk_every = Some(every);
+                }
+                "--check-solar-system" => parsed.check_solar_system = true,
+                flag if flag.starts_with('-') => return Err(format!("unknown option `{}`", flag)),
+                _ => {
+                    if parsed.scenario.is_some() {
+                        return Err(format!("unexpected argument `{}`", arg));
+                    }
+                    parsed.scenario = Some(arg);
+                }
+            }
+        }
+        // the window exports with `V` instead
+        if parsed.vtk_every.is_some() && parsed.headless.is_none() {
+            return Err("`--vtk-every` only works together with `--headless`".to_string());
+        }
+        Ok(Some(parsed))
+    }
+
+    pub fn apply(&self, config: &mut SimConfig) {
+        if let Some(world_width) = self.world_width {
+            config.world_width = world_width;
+        }
+        if let Some(world_height) = self.world_height {
+            config.world_height = world_height;
+        }
+        if let Some(g) = self.g {
+            config.g = g;
+        }
+        if let Some(coulomb) = self.coulomb {
+            config.coulomb = coulomb;
+        }
+        if let Some(softening) = self.softening {
+            config.softening = softening;
+        }
+        if let Some(theta) = self.theta {
+            config.theta = theta;
+        }
+        if let Some(dt) = self.dt {
+            config.dt = dt;
+        }
+        if let Some(integrator) = self.integrator {
+            config.integrator = integrator;
+        }
+        if let Some(seed) = self.seed {
+            config.seed = Some(seed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Args>, String> {
+        Args::parse_from(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn no_arguments_keep_the_scenario_config() {
+        let args = parse(&[]).unwrap().unwrap();
+        assert_eq!(args.window_size, Vector2::new(WIDTH, HEIGHT));
+        assert_eq!(args.scenario, None);
+        assert_eq!(args.headless, None);
+        assert!(!args.record && !args.log && !args.check_solar_system);
+
+        let scenario_config = SimConfig {
+            g: 2.0,
+            dt: 0.1,
+            seed: Some(5),
+            ..SimConfig::default()
+        };
+        let mut config = scenario_config;
+        args.apply(&mut config);
+        assert_eq!(config, scenario_config);
+    }
+
+    #[test]
+    fn options_override_the_scenario_config() {
+        let args = parse(&[
+            "scenarios/disk.toml",
+            "--width",
+            "800",
+            "--g",
+            "0.5",
+            "--softening",
+            "0",
+            "--dt",
+            "0.25",
+            "--integrator",
+            "leapfrog",
+            "--seed",
+            "42",
+            "--particles",
+            "300",
+            "--headless",
+            "100",
+            "--vtk-every",
+            "10",
+            "--log",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(args.scenario.as_deref(), Some("scenarios/disk.toml"));
+        assert_eq!(args.window_size, Vector2::new(800.0, HEIGHT));
+        assert_eq!(args.particles, Some(300));
+        assert_eq!((args.headless, args.vtk_every), (Some(100), Some(10)));
+        assert!(args.log);
+
+        let mut config = SimConfig::default();
+        args.apply(&mut config);
+        assert_eq!(config.g, 0.5);
+        assert_eq!(config.softening, 0.0);
+        assert_eq!(config.dt, 0.25);
+        assert_eq!(config.integrator, Integrator::Leapfrog);
+        assert_eq!(config.seed, Some(42));
+        assert_eq!(config.theta, SimConfig::default().theta);
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        assert!(parse(&["--help"]).unwrap().is_none());
+        for (args, message) in [
+            (&["--dt", "-1"][..], "`--dt` has to be positive"),
+            (&["--seed"][..], "`--seed` expects a value"),
+            (&["--theta", "wide"][..], "`wide` is not a valid value"),
+            (&["--fast"][..], "unknown option `--fast`"),
+            (&["a.toml", "b.toml"][..], "unexpected argument `b.toml`"),
+            (
+                &["--vtk-every", "10"][..],
+                "only works together with `--headless`",
+            ),
+        ] {
+            let error = parse(args).err().unwrap();
+            assert!(error.contains(message), "{:?}: {}", args, error);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::consts::{WORLD_HEIGHT, WORLD_WIDTH};
+use crate::cosmology::Cosmology;
+use crate::sph::SphConfig;
+use nalgebra::Vector2;
+use serde::Deserialize;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Integrator {
+    // semi-implicit Euler: kick with forces at the current positions, then drift
+    Euler,
+    // kick-drift-kick, second order and time-reversible
+    Leapfrog,
+}
+
+impl FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "euler" => Ok(Self::Euler),
+            "leapfrog" => Ok(Self::Leapfrog),
+            _ => Err(format!(
+                "unknown integrator `{}`, expected `euler` or `leapfrog`",
+                name
+            )),
+        }
+    }
+}
+
+// everything the physics depends on, so two simulations with different
+// constants can live in one process
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimConfig {
+    pub g: f32,
+    // Coulomb constant k, only charged particles feel it
+    pub coulomb: f32,
+    pub softening: f32,
+    // Barnes-Hut opening angle, nodes with `width / distance < theta` are treated as one body
+    pub theta: f32,
+    pub dt: f32,
+    pub integrator: Integrator,
+    pub world_width: f32,
+    pub world_height: f32,
+    // seed of the random initial conditions, a random one is picked (and recorded) when unset
+    pub seed: Option<u64>,
+    // comoving mode with an expanding universe, static space when unset
+    pub cosmology: Option<Cosmology>,
+    // equation of state and viscosity of the gas particles
+    pub sph: SphConfig,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            g: 0.04,
+            coulomb: 1.0,
+            softening: 2.5,
+            theta: 0.5,
+            dt: 1.0,
+            integrator: Integrator::Euler,
+            world_width: WORLD_WIDTH,
+            world_height: WORLD_HEIGHT,
+            seed: None,
+            cosmology: None,
+            sph: SphConfig::default(),
+        }
+    }
+}
+
+impl SimConfig {
+    pub fn world_size(&self) -> Vector2<f32> {
+        Vector2::new(self.world_width, self.world_height)
+    }
+
+    // comoving separations grow with the scale factor, so the comoving force is
+    // the physical one divided by a³, which is the same as a smaller G
+    pub fn at_scale_factor(&self, scale_factor: f32) -> SimConfig {
+        SimConfig {
+            g: self.g / scale_factor.powi(3),
+            ..*self
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let positive = [
+            ("dt", self.dt),
+            ("theta", self.theta),
+            ("world_width", self.world_width),
+            ("world_height", self.world_height),
+        ];
+        for (field, value) in positive {
+            if !(value > 0.0 && value.is_finite()) {
+                return Err(format!("`{}` has to be positive, got {}", field, value));
+            }
+        }
+        for (field, value) in [
+            ("g", self.g),
+            ("coulomb", self.coulomb),
+            ("softening", self.softening),
+        ] {
+            if !(value >= 0.0 && value.is_finite()) {
+                return Err(format!("`{}` can't be negative, got {}", field, value));
+            }
+        }
+        if let Some(cosmology) = &self.cosmology {
+            cosmology
+                .validate()
+                .map_err(|message| format!("cosmology: {}", message))?;
+        }
+        self.sph
+            .validate()
+            .map_err(|message| format!("sph: {}", message))?;
+        Ok(())
+    }
+}
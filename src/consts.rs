@@ -1,11 +1,7 @@
-use nalgebra::Vector2;
 pub const WIDTH: f32 = 1024.0;
 pub const HEIGHT: f32 = 1024.0;
 pub const WORLD_WIDTH: f32 = 512.0;
 pub const WORLD_HEIGHT: f32 = 512.0;
-pub const MAX_ZOOM: f32 = WIDTH / WORLD_WIDTH;
 pub const MOUSE_AREA: f32 = 100.0;
-pub const LOWER_BOUND: Vector2<f32> = Vector2::new(MOUSE_AREA, MOUSE_AREA);
-pub const UPPER_BOUND: Vector2<f32> = Vector2::new(WIDTH - MOUSE_AREA, HEIGHT - MOUSE_AREA);
-pub const G: f32 = 0.04;
-pub const SOFTENING: f32 = 2.5;
+pub const DIAGNOSTICS_INTERVAL: u32 = 100;
+pub const LOG_INTERVAL: u32 = 1;
@@ -0,0 +1,424 @@
+use crate::config::SimConfig;
+use crate::generators::gaussian;
+use crate::particle::Particle;
+use crate::potentials::ExternalPotential;
+use crate::quadtree::QuadTree;
+use crate::utils::create_quadtree;
+use nalgebra::{Complex, Vector2};
+use rand::Rng;
+use serde::Deserialize;
+use std::f64::consts::PI;
+
+// Friedmann model of a matter + cosmological constant universe, curvature makes up
+// the rest. In the comoving mode positions are comoving, the scale factor `a` grows
+// from 1 / (1 + initial_redshift) and gravity is weaker by a³ as the universe expands.
+// The comoving equations only hold for the gravity of the perturbations around the
+// mean density, so the gravity of the mean density is subtracted, see `MeanDensity`
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cosmology {
+    // H0, the expansion rate at a = 1, per time unit
+    pub hubble: f32,
+    pub omega_matter: f32,
+    #[serde(default)]
+    pub omega_lambda: f32,
+    pub initial_redshift: f32,
+}
+
+impl Cosmology {
+    pub fn initial_scale_factor(&self) -> f32 {
+        1.0 / (1.0 + self.initial_redshift)
+    }
+
+    // H(a) = H0 sqrt(Ωm / a³ + Ωk / a² + ΩΛ)
+    pub fn hubble_rate(&self, scale_factor: f32) -> f32 {
+        let a = scale_factor as f64;
+        let omega_curvature = 1.0 - self.omega_matter as f64 - self.omega_lambda as f64;
+        let squared = self.omega_matter as f64 / a.powi(3)
+            + omega_curvature / a.powi(2)
+            + self.omega_lambda as f64;
+        (self.hubble as f64 * squared.max(0.0).sqrt()) as f32
+    }
+
+    // one RK4 step of da/dt = a H(a)
+    pub fn advance(&self, scale_factor: f32, dt: f32) -> f32 {
+        let rate = |a: f64| a * self.hubble_rate(a as f32) as f64;
+        let (a, dt) = (scale_factor as f64, dt as f64);
+        let k1 = rate(a);
+        let k2 = rate(a + k1 * dt / 2.0);
+        let k3 = rate(a + k2 * dt / 2.0);
+        let k4 = rate(a + k3 * dt);
+        (a + (k1 + 2.0 * k2 + 2.0 * k3 + k4) * dt / 6.0) as f32
+    }
+
+    pub fn redshift(scale_factor: f32) -> f32 {
+        1.0 / scale_factor - 1.0
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.hubble > 0.0 && self.hubble.is_finite()) {
+            return Err(format!("`hubble` has to be positive, got {}", self.hubble));
+        }
+        for (field, value) in [
+            ("omega_matter", self.omega_matter),
+            ("omega_lambda", self.omega_lambda),
+            ("initial_redshift", self.initial_redshift),
+        ] {
+            if !(value >= 0.0 && value.is_finite()) {
+                return Err(format!("`{}` can't be negative, got {}", field, value));
+            }
+        }
+        if self.hubble_rate(self.initial_scale_factor()) <= 0.0 {
+            return Err("the universe doesn't expand at `initial_redshift`".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Gaussian random field with the power spectrum P(k) ∝ k^spectral_index exp(-(k smoothing)²)
+pub struct FieldSpec {
+    pub spectral_index: f32,
+    // length below which the power is cut off, no cutoff when zero
+    pub smoothing: f32,
+    // rms displacement of the particles from the lattice, sets the amplitude of the field
+    pub displacement_rms: f32,
+    // velocities are `hubble` times the displacement, the growing mode of a
+    // matter dominated universe. Zero starts everything at rest
+    pub hubble: f32,
+}
+
+// in-place radix-2 Cooley-Tukey, `data.len()` has to be a power of two
+fn fft(data: &mut [Complex<f64>], inverse: bool) {
+    let n = data.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2.0 * PI / len as f64;
+        let root = Complex::new(angle.cos(), angle.sin());
+        for start in (0..n).step_by(len) {
+            let mut twiddle = Complex::new(1.0, 0.0);
+            for k in 0..len / 2 {
+                let even = data[start + k];
+                let odd = data[start + k + len / 2] * twiddle;
+                data[start + k] = even + odd;
+                data[start + k + len / 2] = even - odd;
+                twiddle *= root;
+            }
+        }
+        len <<= 1;
+    }
+
+    if inverse {
+        for value in data.iter_mut() {
+            *value /= n as f64;
+        }
+    }
+}
+
+// row-major `size` x `size` grid
+fn fft_2d(data: &mut [Complex<f64>], size: usize, inverse: bool) {
+    for row in data.chunks_mut(size) {
+        fft(row, inverse);
+    }
+    let mut column = vec![Complex::new(0.0, 0.0); size];
+    for x in 0..size {
+        for y in 0..size {
+            column[y] = data[y * size + x];
+        }
+        fft(&mut column, inverse);
+        for y in 0..size {
+            data[y * size + x] = column[y];
+        }
+    }
+}
+
+// wavenumber of the i-th FFT bin, negative frequencies in the upper half
+fn wavenumber(i: usize, size: usize, box_size: f64) -> f64 {
+    let frequency = if i <= size / 2 {
+        i as f64
+    } else {
+        i as f64 - size as f64
+    };
+    2.0 * PI * frequency / box_size
+}
+
+// Zel'dovich displacement field ψ of a random field on a `grid` x `grid` lattice,
+// δ = -∇·ψ, so ψ_k = i k δ_k / k². Returned in world units
+fn displacement_field(
+    rng: &mut impl Rng,
+    box_size: f32,
+    grid: usize,
+    field: &FieldSpec,
+) -> Vec<Vector2<f32>> {
+    let box_size = box_size as f64;
+    // white noise has a flat spectrum, shaping it keeps the field real
+    let mut density: Vec<Complex<f64>> = (0..grid * grid)
+        .map(|_| Complex::new(gaussian(rng) as f64, 0.0))
+        .collect();
+    fft_2d(&mut density, grid, false);
+
+    let mut displacement_x = vec![Complex::new(0.0, 0.0); grid * grid];
+    let mut displacement_y = vec![Complex::new(0.0, 0.0); grid * grid];
+    for y in 0..grid {
+        for x in 0..grid {
+            // the mean and the Nyquist modes have no real displacement
+            if (x == 0 && y == 0) || x == grid / 2 || y == grid / 2 {
+                continue;
+            }
+            let (kx, ky) = (wavenumber(x, grid, box_size), wavenumber(y, grid, box_size));
+            let k_squared = kx * kx + ky * ky;
+            let amplitude = k_squared.powf(field.spectral_index as f64 / 4.0)
+                * (-k_squared * (field.smoothing as f64).powi(2) / 2.0).exp();
+            let delta = density[y * grid + x] * amplitude;
+            displacement_x[y * grid + x] = Complex::new(0.0, kx / k_squared) * delta;
+            displacement_y[y * grid + x] = Complex::new(0.0, ky / k_squared) * delta;
+        }
+    }
+    fft_2d(&mut displacement_x, grid, true);
+    fft_2d(&mut displacement_y, grid, true);
+
+    let mean_squared = displacement_x
+        .iter()
+        .zip(&displacement_y)
+        .map(|(x, y)| x.re * x.re + y.re * y.re)
+        .sum::<f64>()
+        / (grid * grid) as f64;
+    let scale = if mean_squared > 0.0 {
+        field.displacement_rms as f64 / mean_squared.sqrt()
+    } else {
+        0.0
+    };
+    displacement_x
+        .iter()
+        .zip(&displacement_y)
+        .map(|(x, y)| Vector2::new((x.re * scale) as f32, (y.re * scale) as f32))
+        .collect()
+}
+
+// cell centers of a `grid` x `grid` lattice filling a `box_size` square at `center`, row by row
+fn lattice_positions(center: Vector2<f32>, box_size: f32, grid: usize) -> Vec<Vector2<f32>> {
+    let spacing = box_size / grid as f32;
+    let corner = center - Vector2::new(box_size, box_size) / 2.0;
+    (0..grid * grid)
+        .map(|index| {
+            let (x, y) = (index % grid, index / grid);
+            corner + Vector2::new(x as f32 + 0.5, y as f32 + 0.5) * spacing
+        })
+        .collect()
+}
+
+// `grid` x `grid` lattice filling a `box_size` square at `center`, displaced by a
+// Gaussian random field with the Zel'dovich approximation. `grid` has to be a power
+// of two. The field is periodic over the box, but the box itself evolves in open space
+pub fn spawn_random_field(
+    particles: &mut Vec<Particle>,
+    rng: &mut impl Rng,
+    center: Vector2<f32>,
+    box_size: f32,
+    grid: u32,
+    particle_mass: f32,
+    field: &FieldSpec,
+) {
+    let grid = grid as usize;
+    let displacement = displacement_field(rng, box_size, grid, field);
+    for (index, lattice_pos) in lattice_positions(center, box_size, grid)
+        .into_iter()
+        .enumerate()
+    {
+        particles.push(Particle::new(
+            lattice_pos + displacement[index],
+            displacement[index] * field.hubble,
+            particle_mass,
+            0.00001,
+            index,
+        ));
+    }
+}
+
+// mean density of a random field box in the comoving mode. It only drives the expansion,
+// so its gravity is taken away from every particle and what's left is the pull of the
+// perturbations, the role the -(ä/a) x term plays in a 3D universe. The mean density is
+// the undisplaced lattice, a lattice that stays undisplaced feels no force at all
+pub struct MeanDensity {
+    lattice: QuadTree,
+}
+
+impl MeanDensity {
+    pub fn new(
+        center: Vector2<f32>,
+        box_size: f32,
+        grid: u32,
+        particle_mass: f32,
+        world_size: Vector2<f32>,
+    ) -> Self {
+        let lattice: Vec<Particle> = lattice_positions(center, box_size, grid as usize)
+            .into_iter()
+            .enumerate()
+            .map(|(index, pos)| {
+                Particle::new(pos, Vector2::new(0.0, 0.0), particle_mass, 0.00001, index)
+            })
+            .collect();
+        // the same bounds as the tree of the particles, so both are approximated alike
+        Self {
+            lattice: create_quadtree(&lattice, world_size),
+        }
+    }
+
+    fn probe(pos: Vector2<f32>) -> Particle {
+        Particle::new(pos, Vector2::new(0.0, 0.0), 1.0, 0.00001, usize::MAX)
+    }
+}
+
+impl ExternalPotential for MeanDensity {
+    fn acceleration(&self, pos: Vector2<f32>, config: &SimConfig) -> Vector2<f32> {
+        let mut probe = Self::probe(pos);
+        self.lattice.calculate_force(&mut probe, config);
+        -probe.net_force
+    }
+
+    fn potential(&self, pos: Vector2<f32>, config: &SimConfig) -> f32 {
+        -self.lattice.calculate_potential(&Self::probe(pos), config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generators::seeded_rng;
+    use crate::scenario::Scenario;
+    use crate::simulation::Simulation;
+
+    // largest distance a particle moved from its start
+    fn largest_drift(
+        potentials_of: impl Fn(&Scenario, &SimConfig) -> Vec<Box<dyn ExternalPotential>>,
+    ) -> f32 {
+        let scenario = Scenario::parse(
+            "[config]
+            integrator = \"leapfrog\"
+            softening = 1.0
+
+            [config.cosmology]
+            hubble = 0.003
+            omega_matter = 1.0
+            initial_redshift = 4.0
+
+            [[random_field]]
+            center = [256.0, 256.0]
+            box_size = 128.0
+            grid = 16
+            particle_mass = 0.01
+            spectral_index = -1.0
+            displacement_rms = 0.0",
+        )
+        .unwrap();
+        let config = scenario.config;
+        let particles = scenario.build(&config, &mut seeded_rng(1)).unwrap();
+        let start: Vec<_> = particles
+            .iter()
+            .map(|particle| (particle.index, particle.pos))
+            .collect();
+        let mut simulation = Simulation::new(
+            particles,
+            config,
+            potentials_of(&scenario, &config),
+            Vec::new(),
+            Vec::new(),
+        );
+        for _ in 0..50 {
+            simulation.update();
+        }
+        start
+            .iter()
+            .map(|(index, pos)| {
+                let particle = simulation
+                    .particles
+                    .iter()
+                    .find(|particle| particle.index == *index)
+                    .unwrap();
+                (particle.pos - pos).norm()
+            })
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn uniform_lattice_stays_at_rest_in_comoving_coordinates() {
+        let drift = largest_drift(|scenario, config| scenario.potentials(config));
+        assert!(drift < 1e-3, "a particle moved {}", drift);
+        // the mean density alone would pull the box together
+        let collapse = largest_drift(|_, _| Vec::new());
+        assert!(collapse > 0.1, "the box only contracted by {}", collapse);
+    }
+
+    fn distance(a: Complex<f64>, b: Complex<f64>) -> f64 {
+        (a - b).norm_sqr().sqrt()
+    }
+
+    #[test]
+    fn inverse_fft_returns_the_input() {
+        let mut rng = seeded_rng(3);
+        let input: Vec<Complex<f64>> = (0..64)
+            .map(|_| Complex::new(gaussian(&mut rng) as f64, gaussian(&mut rng) as f64))
+            .collect();
+        let mut data = input.clone();
+        fft(&mut data, false);
+        // the transform of a random signal is nothing like it
+        assert!(distance(data[5], input[5]) > 1e-3);
+        fft(&mut data, true);
+        for (result, expected) in data.iter().zip(&input) {
+            assert!(distance(*result, *expected) < 1e-12);
+        }
+
+        // a single spike has a flat spectrum
+        let mut spike = vec![Complex::new(0.0, 0.0); 16];
+        spike[0] = Complex::new(1.0, 0.0);
+        fft(&mut spike, false);
+        assert!(spike
+            .iter()
+            .all(|value| distance(*value, Complex::new(1.0, 0.0)) < 1e-12));
+
+        let mut grid = input.clone();
+        fft_2d(&mut grid, 8, false);
+        fft_2d(&mut grid, 8, true);
+        for (result, expected) in grid.iter().zip(&input) {
+            assert!(distance(*result, *expected) < 1e-12);
+        }
+    }
+
+    #[test]
+    fn displacement_has_zero_mean_and_the_requested_rms() {
+        let displacement = |displacement_rms| {
+            let field = FieldSpec {
+                spectral_index: -1.0,
+                smoothing: 2.0,
+                displacement_rms,
+                hubble: 0.0,
+            };
+            displacement_field(&mut seeded_rng(5), 64.0, 32, &field)
+        };
+        let small = displacement(0.5);
+        let large = displacement(2.0);
+
+        let count = small.len() as f32;
+        let mean = small.iter().sum::<Vector2<f32>>() / count;
+        let rms = (small.iter().map(|d| d.norm_squared()).sum::<f32>() / count).sqrt();
+        assert!(mean.norm() < 1e-5, "mean {}", mean);
+        assert!((rms - 0.5).abs() < 1e-4, "rms {}", rms);
+        // the same seed gives the same field, only scaled
+        for (small, large) in small.iter().zip(&large) {
+            assert!((large - small * 4.0).norm() < 1e-5);
+        }
+    }
+}
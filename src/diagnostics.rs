@@ -0,0 +1,344 @@
+use crate::config::SimConfig;
+use crate::cosmology::Cosmology;
+use crate::forces::ForceField;
+use crate::particle::Particle;
+use crate::potentials::ExternalPotential;
+use crate::quadtree::QuadTree;
+use crate::sinks::Accretion;
+use crate::utils::timestamp;
+use nalgebra::Vector2;
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Diagnostics {
+    pub kinetic_energy: f32,
+    pub potential_energy: f32,
+    // internal energy of the gas, shocks turn kinetic energy into it
+    pub thermal_energy: f32,
+    pub total_energy: f32,
+    pub momentum: Vector2<f32>,
+    pub angular_momentum: f32,
+    pub center_of_mass: Vector2<f32>,
+    pub virial_ratio: f32,
+    // swallowed by all the sinks so far
+    pub accreted_mass: f32,
+}
+
+impl Diagnostics {
+    // `qt` has to be built from the same `particles` positions,
+    // otherwise potential energy won't match the kinetic one
+    pub fn compute(
+        particles: &[Particle],
+        qt: &QuadTree,
+        config: &SimConfig,
+        potentials: &[Box<dyn ExternalPotential>],
+        fields: &[ForceField],
+    ) -> Self {
+        let total_mass: f32 = particles.iter().map(|p| p.mass).sum();
+        if particles.is_empty() || total_mass == 0.0 {
+            return Self::default();
+        }
+
+        let momentum: Vector2<f32> = particles.iter().map(|p| p.vel * p.mass).sum();
+        let center_of_mass: Vector2<f32> = particles
+            .iter()
+            .map(|p| p.pos * p.mass)
+            .sum::<Vector2<f32>>()
+            / total_mass;
+        let center_of_mass_vel = momentum / total_mass;
+
+        let kinetic_energy: f32 = particles
+            .par_iter()
+            .map(|p| 0.5 * p.mass * p.vel.norm_squared())
+            .sum();
+        // every pair is counted twice when summing over all particles
+        let pair_energy: f32 = 0.5
+            * particles
+                .par_iter()
+                .map(|p| qt.calculate_potential(p, config))
+                .sum::<f32>();
+        let external_energy: f32 = particles
+            .par_iter()
+            .map(|p| {
+                let potential: f32 = potentials
+                    .iter()
+                    .map(|potential| potential.potential(p.pos, config))
+                    .sum();
+                let field: f32 = fields.iter().map(|field| field.potential(p.pos)).sum();
+                p.mass * (potential + field)
+            })
+            .sum();
+        let potential_energy = pair_energy + external_energy;
+        let thermal_energy: f32 = particles
+            .iter()
+            .filter_map(|p| p.gas().map(|gas| p.mass * gas.internal_energy))
+            .sum();
+        let angular_momentum: f32 = particles
+            .iter()
+            .map(|p| {
+                let r = p.pos - center_of_mass;
+                let v = p.vel - center_of_mass_vel;
+                p.mass * (r.x * v.y - r.y * v.x)
+            })
+            .sum();
+
+        // bulk motion of the whole system doesn't count towards virial equilibrium
+        let internal_kinetic_energy =
+            kinetic_energy - 0.5 * total_mass * center_of_mass_vel.norm_squared();
+        let virial_ratio = if potential_energy != 0.0 {
+            2.0 * internal_kinetic_energy / potential_energy.abs()
+        } else {
+            0.0
+        };
+
+        Self {
+            kinetic_energy,
+            potential_energy,
+            thermal_energy,
+            total_energy: kinetic_energy + potential_energy + thermal_energy,
+            momentum,
+            angular_momentum,
+            center_of_mass,
+            virial_ratio,
+            accreted_mass: particles
+                .iter()
+                .filter_map(|p| p.sink().map(|sink| sink.accreted_mass))
+                .sum(),
+        }
+    }
+
+    pub fn energy_drift(&self, initial: &Diagnostics) -> f32 {
+        if initial.total_energy == 0.0 {
+            return 0.0;
+        }
+        (self.total_energy - initial.total_energy) / initial.total_energy.abs()
+    }
+
+    pub fn print(&self, step: u32, initial: &Diagnostics) {
+        println!(
+            "step {}: E = {:.4} (K = {:.4}, U = {:.4}, Q = {:.4}, drift = {:.3e}), |P| = {:.4e}, L = {:.4e}, com = ({:.2}, {:.2}), 2K/|U| = {:.3}",
+            step,
+            self.total_energy,
+            self.kinetic_energy,
+            self.potential_energy,
+            self.thermal_energy,
+            self.energy_drift(initial),
+            self.momentum.norm(),
+            self.angular_momentum,
+            self.center_of_mass.x,
+            self.center_of_mass.y,
+            self.virial_ratio,
+        );
+    }
+}
+
+pub struct StepStats {
+    pub step: u32,
+    pub time: f32,
+    pub dt: f32,
+    pub particle_count: usize,
+    pub max_speed: f32,
+    pub tree_depth: u32,
+    pub tree_ms: f32,
+    pub force_ms: f32,
+    pub diagnostics_ms: f32,
+    pub scale_factor: f32,
+}
+
+pub struct DiagnosticsLog {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    // created next to the log with the first accretion
+    accretion_writer: Option<BufWriter<File>>,
+    started: Instant,
+    seed: String,
+}
+
+impl DiagnosticsLog {
+    // goes next to the recorded videos, named the same way
+    pub fn create(seed: Option<u64>) -> io::Result<Self> {
+        Self::create_at(PathBuf::from(format!("results/{}.csv", timestamp())), seed)
+    }
+
+    fn create_at(path: PathBuf, seed: Option<u64>) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        writeln!(
+            writer,
+            "step,time,dt,particle_count,kinetic_energy,potential_energy,thermal_energy,\
+             total_energy,energy_drift,momentum_x,momentum_y,angular_momentum,virial_ratio,\
+             accreted_mass,max_speed,tree_depth,tree_ms,force_ms,diagnostics_ms,scale_factor,redshift,\
+             wall_time_s,seed"
+        )?;
+        Ok(Self {
+            writer,
+            path,
+            accretion_writer: None,
+            seed: seed.map(|seed| seed.to_string()).unwrap_or_default(),
+            started: Instant::now(),
+        })
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    pub fn write(
+        &mut self,
+        stats: &StepStats,
+        diagnostics: &Diagnostics,
+        initial: &Diagnostics,
+    ) -> io::Result<()> {
+        writeln!(
+            self.writer,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{:.3},{:.3},{:.3},{},{},{:.3},{}",
+            stats.step,
+            stats.time,
+            stats.dt,
+            stats.particle_count,
+            diagnostics.kinetic_energy,
+            diagnostics.potential_energy,
+            diagnostics.thermal_energy,
+            diagnostics.total_energy,
+            diagnostics.energy_drift(initial),
+            diagnostics.momentum.x,
+            diagnostics.momentum.y,
+            diagnostics.angular_momentum,
+            diagnostics.virial_ratio,
+            diagnostics.accreted_mass,
+            stats.max_speed,
+            stats.tree_depth,
+            stats.tree_ms,
+            stats.force_ms,
+            stats.diagnostics_ms,
+            stats.scale_factor,
+            Cosmology::redshift(stats.scale_factor),
+            self.started.elapsed().as_secs_f32(),
+            self.seed,
+        )
+    }
+
+    pub fn write_accretions(&mut self, accretions: &[Accretion]) -> io::Result<()> {
+        if self.accretion_writer.is_none() {
+            let path = self.path.with_extension("accretion.csv");
+            let mut writer = BufWriter::new(File::create(path)?);
+            writeln!(writer, "step,time,sink_index,particle_index,mass,sink_mass")?;
+            self.accretion_writer = Some(writer);
+        }
+        let writer = self.accretion_writer.as_mut().unwrap();
+        for accretion in accretions {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                accretion.step,
+                accretion.time,
+                accretion.sink,
+                accretion.particle,
+                accretion.mass,
+                accretion.sink_mass,
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(writer) = self.accretion_writer.as_mut() {
+            writer.flush()?;
+        }
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_quadtree;
+
+    #[test]
+    fn two_bodies_match_the_analytic_energy() {
+        let config = SimConfig::default();
+        let particles = vec![
+            Particle::new(
+                Vector2::new(200.0, 250.0),
+                Vector2::new(0.0, 0.3),
+                2.0,
+                1.0,
+                0,
+            ),
+            Particle::new(
+                Vector2::new(250.0, 250.0),
+                Vector2::new(0.0, -0.2),
+                3.0,
+                1.0,
+                1,
+            ),
+        ];
+        let qt = create_quadtree(&particles, config.world_size());
+        let diagnostics = Diagnostics::compute(&particles, &qt, &config, &[], &[]);
+
+        let kinetic_energy = 0.5 * 2.0 * 0.3f32.powi(2) + 0.5 * 3.0 * 0.2f32.powi(2);
+        let distance = (50.0f32.powi(2) + config.softening.powi(2)).sqrt();
+        let potential_energy = -config.g * 2.0 * 3.0 / distance;
+        assert!((diagnostics.kinetic_energy - kinetic_energy).abs() < 1e-6);
+        assert!((diagnostics.potential_energy - potential_energy).abs() < 1e-6);
+        assert!((diagnostics.total_energy - kinetic_energy - potential_energy).abs() < 1e-6);
+        assert!(diagnostics.momentum.norm() < 1e-6);
+    }
+
+    #[test]
+    fn log_row_matches_the_header() {
+        let path = std::env::temp_dir().join(format!("{}_log.csv", std::process::id()));
+        let mut log = DiagnosticsLog::create_at(path.clone(), Some(7)).unwrap();
+        let stats = StepStats {
+            step: 20,
+            time: 10.0,
+            dt: 0.5,
+            particle_count: 3,
+            max_speed: 2.5,
+            tree_depth: 4,
+            tree_ms: 1.0,
+            force_ms: 2.0,
+            diagnostics_ms: 0.5,
+            scale_factor: 0.5,
+        };
+        let initial = Diagnostics {
+            total_energy: -4.0,
+            ..Diagnostics::default()
+        };
+        let diagnostics = Diagnostics {
+            kinetic_energy: 1.0,
+            potential_energy: -6.0,
+            total_energy: -5.0,
+            momentum: Vector2::new(0.25, -0.5),
+            accreted_mass: 1.5,
+            ..Diagnostics::default()
+        };
+        log.write(&stats, &diagnostics, &initial).unwrap();
+        log.finish().unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let header: Vec<&str> = lines[0].split(',').collect();
+        let row: Vec<&str> = lines[1].split(',').collect();
+        assert_eq!(header.len(), row.len());
+        let column = |name: &str| row[header.iter().position(|&h| h == name).unwrap()];
+        assert_eq!(column("step"), "20");
+        assert_eq!(column("time"), "10");
+        assert_eq!(column("particle_count"), "3");
+        assert_eq!(column("total_energy"), "-5");
+        assert_eq!(column("energy_drift"), "-0.25");
+        assert_eq!(column("momentum_x"), "0.25");
+        assert_eq!(column("momentum_y"), "-0.5");
+        assert_eq!(column("accreted_mass"), "1.5");
+        assert_eq!(column("max_speed"), "2.5");
+        assert_eq!(column("tree_depth"), "4");
+        assert_eq!(column("force_ms"), "2.000");
+        assert_eq!(column("redshift"), "1");
+        assert_eq!(column("seed"), "7");
+    }
+}
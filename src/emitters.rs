@@ -0,0 +1,263 @@
+use crate::generators::{gaussian, random_direction};
+use crate::particle::Particle;
+use nalgebra::Vector2;
+use rand::Rng;
+use serde::Deserialize;
+
+// how emitted velocities scatter around the emitter `velocity`,
+// e.g. `velocity_distribution = { distribution = "cone", angle = 10.0 }`
+#[derive(Clone, Copy, PartialEq, Debug, Default, Deserialize)]
+#[serde(tag = "distribution", rename_all = "snake_case", deny_unknown_fields)]
+pub enum VelocityDistribution {
+    // every particle leaves with exactly `velocity`
+    #[default]
+    Fixed,
+    // random velocities with `sigma` standard deviation along each axis, e.g. a hot wind
+    Gaussian {
+        sigma: f32,
+    },
+    // `velocity` turned by up to `angle` degrees either way, e.g. a jet
+    Cone {
+        angle: f32,
+    },
+    // `speed` away from the emitter on top of `velocity`, e.g. a stellar wind
+    Radial {
+        speed: f32,
+    },
+}
+
+impl VelocityDistribution {
+    // `offset` is where the particle appears relative to the emitter
+    fn sample(
+        &self,
+        rng: &mut impl Rng,
+        velocity: Vector2<f32>,
+        offset: Vector2<f32>,
+    ) -> Vector2<f32> {
+        match *self {
+            Self::Fixed => velocity,
+            Self::Gaussian { sigma } => {
+                velocity + Vector2::new(gaussian(rng), gaussian(rng)) * sigma
+            }
+            Self::Cone { angle } => {
+                let (sin, cos) = rng.gen_range(-angle..=angle).to_radians().sin_cos();
+                Vector2::new(
+                    velocity.x * cos - velocity.y * sin,
+                    velocity.x * sin + velocity.y * cos,
+                )
+            }
+            Self::Radial { speed } => {
+                let direction = if offset.norm() > 0.0 {
+                    offset.normalize()
+                } else {
+                    random_direction(rng)
+                };
+                velocity + direction * speed
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let (field, value) = match *self {
+            Self::Fixed => return Ok(()),
+            Self::Gaussian { sigma } => ("sigma", sigma),
+            Self::Cone { angle } => ("angle", angle),
+            Self::Radial { speed } => ("speed", speed),
+        };
+        if value >= 0.0 && value.is_finite() {
+            Ok(())
+        } else {
+            Err(format!("`{}` can't be negative, got {}", field, value))
+        }
+    }
+}
+
+// spawns particles while the simulation runs, e.g. a `[[emitter]]` scenario table
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Emitter {
+    // world position, or the offset from the particle the emitter is attached to
+    pub pos: [f32; 2],
+    // `Particle::index` of the particle the emitter moves with, emitted velocities are
+    // relative to it. The emitter stops once the particle is gone, e.g. swallowed by a sink
+    pub attach: Option<usize>,
+    // particles per time unit
+    pub rate: f32,
+    // radius of the disk around `pos` the particles appear in, can only be zero
+    // with at most one particle per step
+    #[serde(default)]
+    pub spread: f32,
+    #[serde(default)]
+    pub velocity: [f32; 2],
+    #[serde(default)]
+    pub velocity_distribution: VelocityDistribution,
+    pub particle_mass: f32,
+    // fraction of a particle left over from the previous steps
+    #[serde(skip)]
+    pending: f32,
+}
+
+impl Emitter {
+    // spawns the particles due over `dt`, indices continue from `next_index`
+    pub fn emit(
+        &mut self,
+        particles: &mut Vec<Particle>,
+        rng: &mut impl Rng,
+        dt: f32,
+        next_index: &mut usize,
+    ) {
+        let (origin, origin_vel) = match self.attach {
+            Some(index) => match particles.iter().find(|particle| particle.index == index) {
+                Some(parent) => (parent.pos, parent.vel),
+                None => return,
+            },
+            None => (Vector2::default(), Vector2::default()),
+        };
+        let previous = self.pending;
+        self.pending += self.rate * dt;
+        let count = self.pending.floor();
+        self.pending -= count;
+        for i in 0..count as usize {
+            // uniform over the area of the disk
+            let offset = random_direction(rng) * self.spread * rng.gen_range(0.0f32..1.0).sqrt();
+            let vel = self
+                .velocity_distribution
+                .sample(rng, Vector2::from(self.velocity), offset);
+            // the i-th particle left when the pending count reached i + 1 and has been
+            // moving since, particles of the same step don't start on top of each other
+            let age = dt - (i as f32 + 1.0 - previous) / self.rate;
+            particles.push(Particle::new(
+                origin + Vector2::from(self.pos) + offset + vel * age,
+                origin_vel + vel,
+                self.particle_mass,
+                0.00001,
+                *next_index,
+            ));
+            *next_index += 1;
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (field, value) in [
+            ("pos", self.pos[0]),
+            ("pos", self.pos[1]),
+            ("velocity", self.velocity[0]),
+            ("velocity", self.velocity[1]),
+        ] {
+            if !value.is_finite() {
+                return Err(format!("`{}` has to be finite, got {}", field, value));
+            }
+        }
+        for (field, value) in [("rate", self.rate), ("spread", self.spread)] {
+            if !(value >= 0.0 && value.is_finite()) {
+                return Err(format!("`{}` can't be negative, got {}", field, value));
+            }
+        }
+        if !(self.particle_mass > 0.0 && self.particle_mass.is_finite()) {
+            return Err(format!(
+                "`particle_mass` has to be positive, got {}",
+                self.particle_mass
+            ));
+        }
+        self.velocity_distribution.validate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generators::seeded_rng;
+    use crate::scenario::Scenario;
+    use crate::simulation::Simulation;
+    use std::collections::HashSet;
+
+    fn emitter(attach: Option<usize>, rate: f32) -> Emitter {
+        Emitter {
+            pos: [0.0, -4.0],
+            attach,
+            rate,
+            spread: 0.0,
+            velocity: [1.0, 0.0],
+            velocity_distribution: VelocityDistribution::Fixed,
+            particle_mass: 0.01,
+            pending: 0.0,
+        }
+    }
+
+    #[test]
+    fn emitted_particles_stay_finite() {
+        let scenario = Scenario::parse(include_str!("../scenarios/jets.toml")).unwrap();
+        let mut config = scenario.config;
+        config.seed = Some(1);
+        let particles = scenario.build(&config, &mut seeded_rng(1)).unwrap();
+        let mut simulation = Simulation::new(
+            particles,
+            config,
+            scenario.potentials(&config),
+            scenario.field.clone(),
+            scenario.emitter.clone(),
+        );
+        for _ in 0..60 {
+            simulation.update();
+        }
+
+        assert!(simulation.particles.len() > 1000);
+        let indices: HashSet<usize> = simulation
+            .particles
+            .iter()
+            .map(|particle| particle.index)
+            .collect();
+        assert_eq!(indices.len(), simulation.particles.len());
+        for particle in &simulation.particles {
+            assert!(particle.pos.x.is_finite() && particle.pos.y.is_finite());
+            assert!(particle.vel.x.is_finite() && particle.vel.y.is_finite());
+        }
+        assert!(simulation.diagnostics.total_energy.is_finite());
+    }
+
+    #[test]
+    fn emitter_keeps_its_rate() {
+        let mut emitter = emitter(None, 3.0);
+        let mut particles = Vec::new();
+        let mut next_index = 10;
+        let mut rng = seeded_rng(1);
+        // one and a half particles per step
+        for step in 1..=20 {
+            emitter.emit(&mut particles, &mut rng, 0.5, &mut next_index);
+            assert_eq!(particles.len(), step * 3 / 2);
+        }
+        assert_eq!(particles.len(), 30);
+        assert_eq!(next_index, 40);
+        let indices: Vec<usize> = particles.iter().map(|particle| particle.index).collect();
+        assert_eq!(indices, (10..40).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn attached_emitter_moves_with_its_parent() {
+        let mut emitter = emitter(Some(7), 1.0);
+        let mut particles = vec![Particle::new(
+            Vector2::new(100.0, 50.0),
+            Vector2::new(0.0, 2.0),
+            10.0,
+            1.0,
+            7,
+        )];
+        let mut next_index = 8;
+        let mut rng = seeded_rng(1);
+        emitter.emit(&mut particles, &mut rng, 1.0, &mut next_index);
+        particles[0].pos = Vector2::new(130.0, 80.0);
+        emitter.emit(&mut particles, &mut rng, 1.0, &mut next_index);
+
+        assert_eq!(particles.len(), 3);
+        // emitted exactly at the end of the step, so they haven't moved yet
+        assert_eq!(particles[1].pos, Vector2::new(100.0, 46.0));
+        assert_eq!(particles[2].pos, Vector2::new(130.0, 76.0));
+        // velocities are relative to the parent
+        assert_eq!(particles[2].vel, Vector2::new(1.0, 2.0));
+
+        // nothing comes out once the parent is gone
+        particles.remove(0);
+        emitter.emit(&mut particles, &mut rng, 1.0, &mut next_index);
+        assert_eq!(particles.len(), 2);
+    }
+}
@@ -0,0 +1,491 @@
+use crate::config::SimConfig;
+use crate::particle::Particle;
+use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// the columns after `index` are optional, files without them are still read. Particles are
+// neutral without `charge`, gas when their `smoothing_length` is positive and sinks when
+// their `accretion_radius` is
+const COLUMNS: [&str; 11] = [
+    "x",
+    "y",
+    "vx",
+    "vy",
+    "mass",
+    "radius",
+    "index",
+    "charge",
+    "internal_energy",
+    "smoothing_length",
+    "accretion_radius",
+];
+const REQUIRED_COLUMNS: usize = 7;
+
+#[derive(Serialize, Deserialize)]
+struct ParticleRecord {
+    x: f32,
+    y: f32,
+    vx: f32,
+    vy: f32,
+    mass: f32,
+    radius: f32,
+    index: usize,
+    #[serde(default)]
+    charge: f32,
+    #[serde(default)]
+    internal_energy: f32,
+    #[serde(default)]
+    smoothing_length: f32,
+    #[serde(default)]
+    accretion_radius: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ParticleFile {
+    #[serde(default)]
+    units: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    particles: Vec<ParticleRecord>,
+}
+
+impl From<&Particle> for ParticleRecord {
+    fn from(particle: &Particle) -> Self {
+        Self {
+            x: particle.pos.x,
+            y: particle.pos.y,
+            vx: particle.vel.x,
+            vy: particle.vel.y,
+            mass: particle.mass,
+            radius: particle.radius,
+            index: particle.index,
+            charge: particle.charge,
+            internal_energy: particle.gas().map_or(0.0, |gas| gas.internal_energy),
+            smoothing_length: particle.gas().map_or(0.0, |gas| gas.smoothing_length),
+            accretion_radius: particle.sink().map_or(0.0, |sink| sink.accretion_radius),
+        }
+    }
+}
+
+impl ParticleRecord {
+    fn to_particle(&self) -> Particle {
+        let particle = Particle::new(
+            Vector2::new(self.x, self.y),
+            Vector2::new(self.vx, self.vy),
+            self.mass,
+            self.radius,
+            self.index,
+        )
+        .with_charge(self.charge);
+        if self.accretion_radius > 0.0 {
+            particle.with_sink(self.accretion_radius)
+        } else if self.smoothing_length > 0.0 {
+            particle.with_gas(self.internal_energy, self.smoothing_length)
+        } else {
+            particle
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let values = [
+            ("x", self.x),
+            ("y", self.y),
+            ("vx", self.vx),
+            ("vy", self.vy),
+            ("mass", self.mass),
+            ("radius", self.radius),
+            ("charge", self.charge),
+            ("internal_energy", self.internal_energy),
+            ("smoothing_length", self.smoothing_length),
+            ("accretion_radius", self.accretion_radius),
+        ];
+        if let Some((column, _)) = values.iter().find(|(_, v)| !v.is_finite()) {
+            return Err(format!("`{}` is not a finite number", column));
+        }
+        if self.mass <= 0.0 {
+            return Err(format!("`mass` has to be positive, got {}", self.mass));
+        }
+        if self.radius < 0.0 {
+            return Err(format!("`radius` can't be negative, got {}", self.radius));
+        }
+        if self.internal_energy < 0.0 || self.smoothing_length < 0.0 {
+            return Err("gas `internal_energy` and `smoothing_length` can't be negative".into());
+        }
+        if self.accretion_radius < 0.0 {
+            return Err(format!(
+                "`accretion_radius` can't be negative, got {}",
+                self.accretion_radius
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn units(config: &SimConfig) -> BTreeMap<String, String> {
+    BTreeMap::from([
+        ("x, y".to_owned(), "world units".to_owned()),
+        (
+            "vx, vy".to_owned(),
+            format!("world units per time unit (dt = {})", config.dt),
+        ),
+        (
+            "mass".to_owned(),
+            format!("simulation mass (G = {})", config.g),
+        ),
+        ("radius".to_owned(), "world units".to_owned()),
+        ("index".to_owned(), "unique particle id".to_owned()),
+        (
+            "charge".to_owned(),
+            format!("simulation charge (k = {})", config.coulomb),
+        ),
+        (
+            "internal_energy".to_owned(),
+            "specific internal energy of gas particles".to_owned(),
+        ),
+        (
+            "smoothing_length".to_owned(),
+            "world units, zero for collisionless particles".to_owned(),
+        ),
+        (
+            "accretion_radius".to_owned(),
+            "world units, zero for everything but sinks".to_owned(),
+        ),
+    ])
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn export_csv(path: &Path, particles: &[Particle], config: &SimConfig) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(
+        writer,
+        "# positions and radius in world units, velocities in world units per time unit (dt = {}), mass in simulation units (G = {}), charge in simulation units (k = {}), gas particles have a positive smoothing_length and sinks a positive accretion_radius",
+        config.dt, config.g, config.coulomb
+    )?;
+    if let Some(seed) = config.seed {
+        writeln!(writer, "# seed = {}", seed)?;
+    }
+    writeln!(writer, "{}", COLUMNS.join(","))?;
+    for particle in particles {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            particle.pos.x,
+            particle.pos.y,
+            particle.vel.x,
+            particle.vel.y,
+            particle.mass,
+            particle.radius,
+            particle.index,
+            particle.charge,
+            particle.gas().map_or(0.0, |gas| gas.internal_energy),
+            particle.gas().map_or(0.0, |gas| gas.smoothing_length),
+            particle.sink().map_or(0.0, |sink| sink.accretion_radius),
+        )?;
+    }
+    writer.flush()
+}
+
+// lines starting with `#` are comments, the first other line has to be the header
+pub fn import_csv(path: &Path) -> io::Result<Vec<Particle>> {
+    let content = fs::read_to_string(path)?;
+    let mut particles = Vec::new();
+    let mut indices = HashSet::new();
+    let mut columns = 0;
+
+    for (line_number, line) in content.lines().enumerate() {
+        let line_number = line_number + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+
+        if columns == 0 {
+            if fields.len() < REQUIRED_COLUMNS
+                || fields.len() > COLUMNS.len()
+                || fields != COLUMNS[..fields.len()]
+            {
+                return Err(invalid_data(format!(
+                    "line {}: expected header `{}`, got `{}`",
+                    line_number,
+                    COLUMNS.join(","),
+                    line
+                )));
+            }
+            columns = fields.len();
+            continue;
+        }
+
+        if fields.len() != columns {
+            return Err(invalid_data(format!(
+                "line {}: expected {} columns, got {}",
+                line_number,
+                columns,
+                fields.len()
+            )));
+        }
+        let mut values = [0.0; 6];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = fields[i].parse().map_err(|_| {
+                invalid_data(format!(
+                    "line {}: `{}` is not a number in column `{}`",
+                    line_number, fields[i], COLUMNS[i]
+                ))
+            })?;
+        }
+        let index = fields[6].parse().map_err(|_| {
+            invalid_data(format!(
+                "line {}: `{}` is not a valid particle index",
+                line_number, fields[6]
+            ))
+        })?;
+        let mut optional = [0.0; 4];
+        for (i, value) in optional
+            .iter_mut()
+            .enumerate()
+            .take(columns - REQUIRED_COLUMNS)
+        {
+            let column = REQUIRED_COLUMNS + i;
+            *value = fields[column].parse().map_err(|_| {
+                invalid_data(format!(
+                    "line {}: `{}` is not a number in column `{}`",
+                    line_number, fields[column], COLUMNS[column]
+                ))
+            })?;
+        }
+        let record = ParticleRecord {
+            x: values[0],
+            y: values[1],
+            vx: values[2],
+            vy: values[3],
+            mass: values[4],
+            radius: values[5],
+            index,
+            charge: optional[0],
+            internal_energy: optional[1],
+            smoothing_length: optional[2],
+            accretion_radius: optional[3],
+        };
+        record
+            .validate()
+            .map_err(|message| invalid_data(format!("line {}: {}", line_number, message)))?;
+        if !indices.insert(index) {
+            return Err(invalid_data(format!(
+                "line {}: duplicate particle index {}",
+                line_number, index
+            )));
+        }
+        particles.push(record.to_particle());
+    }
+
+    if columns == 0 {
+        return Err(invalid_data(format!(
+            "{} has no header row",
+            path.display()
+        )));
+    }
+    Ok(particles)
+}
+
+pub fn export_json(path: &Path, particles: &[Particle], config: &SimConfig) -> io::Result<()> {
+    let file = ParticleFile {
+        units: units(config),
+        seed: config.seed,
+        particles: particles.iter().map(ParticleRecord::from).collect(),
+    };
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, &file)?;
+    writer.flush()
+}
+
+// line where every object of the top level `particles` array starts, serde doesn't keep
+// the positions of what it parsed, so they are looked up in the text
+fn particle_lines(content: &str) -> Vec<usize> {
+    let mut lines = Vec::new();
+    let mut line = 1;
+    let mut depth = 0;
+    let mut in_particles = false;
+    let (mut in_string, mut escaped) = (false, false);
+    // the last string, the key in front of an array or object
+    let mut string = String::new();
+    for c in content.chars() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+                continue;
+            }
+            string.push(c);
+            continue;
+        }
+        match c {
+            '\n' => line += 1,
+            '"' => {
+                in_string = true;
+                string.clear();
+            }
+            '{' | '[' => {
+                if in_particles && depth == 2 && c == '{' {
+                    lines.push(line);
+                }
+                if depth == 1 && c == '[' && string == "particles" {
+                    in_particles = true;
+                }
+                depth += 1;
+            }
+            '}' | ']' => {
+                depth -= 1;
+                if depth == 1 {
+                    in_particles = false;
+                }
+            }
+            _ => {}
+        }
+    }
+    lines
+}
+
+pub fn import_json(path: &Path) -> io::Result<Vec<Particle>> {
+    let content = fs::read_to_string(path)?;
+    // serde_json errors already point at the line and column
+    let file: ParticleFile = serde_json::from_str(&content)
+        .map_err(|parsing_err| invalid_data(format!("{}: {}", path.display(), parsing_err)))?;
+
+    let lines = particle_lines(&content);
+    let location = |i: usize| match lines.get(i) {
+        Some(line) => format!("line {}: particle #{}", line, i + 1),
+        None => format!("particle #{}", i + 1),
+    };
+    let mut indices = HashSet::new();
+    let mut particles = Vec::new();
+    for (i, record) in file.particles.iter().enumerate() {
+        record
+            .validate()
+            .map_err(|message| invalid_data(format!("{}: {}", location(i), message)))?;
+        if !indices.insert(record.index) {
+            return Err(invalid_data(format!(
+                "{}: duplicate particle index {}",
+                location(i),
+                record.index
+            )));
+        }
+        particles.push(record.to_particle());
+    }
+    Ok(particles)
+}
+
+pub fn import_particles(path: &Path) -> io::Result<Vec<Particle>> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("csv") => import_csv(path),
+        Some("json") => import_json(path),
+        _ => Err(invalid_data(format!(
+            "{} should be a .csv or .json file",
+            path.display()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // writes `content` to a file of its own in the temp folder and imports it
+    fn import(name: &str, content: &str) -> io::Result<Vec<Particle>> {
+        let path = std::env::temp_dir().join(format!("{}_{}", std::process::id(), name));
+        fs::write(&path, content)?;
+        let result = import_particles(&path);
+        fs::remove_file(&path)?;
+        result
+    }
+
+    fn error_message(result: io::Result<Vec<Particle>>) -> String {
+        result.expect_err("the file should be rejected").to_string()
+    }
+
+    #[test]
+    fn csv_imports_with_optional_columns() {
+        let particles = import(
+            "valid.csv",
+            "# comment\nx,y,vx,vy,mass,radius,index,charge\n1,2,3,4,5,1,0,-1\n6,7,8,9,10,1,1,0\n",
+        )
+        .unwrap();
+        assert_eq!(particles.len(), 2);
+        assert_eq!(particles[0].charge, -1.0);
+        assert_eq!(particles[1].pos, Vector2::new(6.0, 7.0));
+    }
+
+    #[test]
+    fn csv_reports_the_line_of_a_bad_value() {
+        let message = error_message(import(
+            "bad_value.csv",
+            "x,y,vx,vy,mass,radius,index\n1,2,3,4,5,1,0\n\n1,2,oops,4,5,1,1\n",
+        ));
+        assert!(message.starts_with("line 4:"), "{}", message);
+        assert!(message.contains("`oops`"), "{}", message);
+    }
+
+    #[test]
+    fn csv_reports_the_line_of_a_wrong_column_count() {
+        let message = error_message(import(
+            "bad_columns.csv",
+            "# comment\nx,y,vx,vy,mass,radius,index\n1,2,3,4,5,1,0\n1,2,3,4,5,1\n",
+        ));
+        assert!(message.starts_with("line 4:"), "{}", message);
+        assert!(message.contains("expected 7 columns, got 6"), "{}", message);
+    }
+
+    #[test]
+    fn json_reports_the_line_of_a_syntax_error() {
+        let message = error_message(import(
+            "bad_syntax.json",
+            "{\n  \"particles\": [\n    { \"x\": 1.0, \"y\": 2.0,, }\n  ]\n}\n",
+        ));
+        assert!(message.contains("line 3"), "{}", message);
+    }
+
+    #[test]
+    fn json_reports_the_line_of_an_invalid_particle() {
+        let particle = |index: u32, mass: f32| {
+            format!(
+                "    {{\n      \"x\": 1.0, \"y\": 2.0, \"vx\": 0.0, \"vy\": 0.0,\n      \"mass\": {}, \"radius\": 1.0, \"index\": {}\n    }}",
+                mass, index
+            )
+        };
+        let file = |particles: &[String]| {
+            format!(
+                "{{\n  \"units\": {{ \"length\": \"[particles]\" }},\n  \"particles\": [\n{}\n  ]\n}}\n",
+                particles.join(",\n")
+            )
+        };
+
+        let valid = import("valid.json", &file(&[particle(0, 1.0), particle(1, 2.0)])).unwrap();
+        assert_eq!(valid.len(), 2);
+
+        let message = error_message(import(
+            "bad_mass.json",
+            &file(&[particle(0, 1.0), particle(1, -2.0)]),
+        ));
+        assert!(message.starts_with("line 8: particle #2:"), "{}", message);
+
+        let message = error_message(import(
+            "duplicate_index.json",
+            &file(&[particle(0, 1.0), particle(1, 1.0), particle(0, 1.0)]),
+        ));
+        assert!(message.starts_with("line 12: particle #3:"), "{}", message);
+        assert!(
+            message.contains("duplicate particle index 0"),
+            "{}",
+            message
+        );
+    }
+}
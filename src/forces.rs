@@ -0,0 +1,146 @@
+use nalgebra::Vector2;
+use serde::Deserialize;
+
+// non-gravitational accelerations acting on every particle,
+// e.g. `kind = "linear_drag"` in a scenario `[[field]]` table
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ForceField {
+    // the same acceleration everywhere, e.g. gravity near a planet surface
+    Uniform {
+        acceleration: [f32; 2],
+    },
+    // a = -coefficient v
+    LinearDrag {
+        coefficient: f32,
+    },
+    // a = -coefficient |v| v
+    QuadraticDrag {
+        coefficient: f32,
+    },
+    // the simulation runs in a frame rotating around `center` with `angular_velocity`
+    // (radians per time unit, positive turns like prograde galaxies spin), which adds
+    // the Coriolis and centrifugal accelerations
+    RotatingFrame {
+        center: [f32; 2],
+        angular_velocity: f32,
+    },
+}
+
+impl ForceField {
+    pub fn acceleration(&self, pos: Vector2<f32>, vel: Vector2<f32>) -> Vector2<f32> {
+        match *self {
+            Self::Uniform { acceleration } => Vector2::from(acceleration),
+            Self::LinearDrag { coefficient } => -vel * coefficient,
+            Self::QuadraticDrag { coefficient } => -vel * vel.norm() * coefficient,
+            Self::RotatingFrame {
+                center,
+                angular_velocity,
+            } => {
+                // prograde is clockwise in world coordinates, so Ω points along -z:
+                // -2 Ω × v = 2ω (-v.y, v.x) and -Ω × (Ω × r) = ω² r
+                let coriolis = Vector2::new(-vel.y, vel.x) * (2.0 * angular_velocity);
+                let centrifugal = (pos - Vector2::from(center)) * angular_velocity.powi(2);
+                coriolis + centrifugal
+            }
+        }
+    }
+
+    // potential energy per unit mass of the conservative part, drag and the
+    // Coriolis force do no work that a potential could describe
+    pub fn potential(&self, pos: Vector2<f32>) -> f32 {
+        match *self {
+            Self::Uniform { acceleration } => -Vector2::from(acceleration).dot(&pos),
+            Self::LinearDrag { .. } | Self::QuadraticDrag { .. } => 0.0,
+            Self::RotatingFrame {
+                center,
+                angular_velocity,
+            } => -0.5 * angular_velocity.powi(2) * (pos - Vector2::from(center)).norm_squared(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let finite = |name: &str, value: f32| {
+            if value.is_finite() {
+                Ok(())
+            } else {
+                Err(format!("`{}` has to be finite, got {}", name, value))
+            }
+        };
+        match *self {
+            Self::Uniform { acceleration } => {
+                finite("acceleration", acceleration[0])?;
+                finite("acceleration", acceleration[1])
+            }
+            Self::LinearDrag { coefficient } | Self::QuadraticDrag { coefficient } => {
+                if coefficient >= 0.0 && coefficient.is_finite() {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "`coefficient` can't be negative, got {}",
+                        coefficient
+                    ))
+                }
+            }
+            Self::RotatingFrame {
+                angular_velocity, ..
+            } => finite("angular_velocity", angular_velocity),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VEL: Vector2<f32> = Vector2::new(3.0, -4.0);
+
+    #[test]
+    fn drag_opposes_the_velocity() {
+        let pos = Vector2::new(10.0, 20.0);
+        let linear = ForceField::LinearDrag { coefficient: 0.5 }.acceleration(pos, VEL);
+        assert!((linear - Vector2::new(-1.5, 2.0)).norm() < 1e-6);
+        assert!((linear.norm() - 0.5 * 5.0).abs() < 1e-6);
+
+        let quadratic = ForceField::QuadraticDrag { coefficient: 0.5 }.acceleration(pos, VEL);
+        assert!((quadratic - Vector2::new(-7.5, 10.0)).norm() < 1e-5);
+        assert!((quadratic.norm() - 0.5 * 25.0).abs() < 1e-5);
+        assert!(quadratic.dot(&VEL) < 0.0);
+    }
+
+    #[test]
+    fn coriolis_turns_the_velocity_without_doing_work() {
+        let center = [5.0, 5.0];
+        let field = ForceField::RotatingFrame {
+            center,
+            angular_velocity: 0.2,
+        };
+        // at the center there is no centrifugal part
+        let coriolis = field.acceleration(Vector2::from(center), VEL);
+        assert!(coriolis.dot(&VEL).abs() < 1e-6);
+        assert!((coriolis.norm() - 2.0 * 0.2 * 5.0).abs() < 1e-6);
+        // Ω points along -z, so moving along +x is pushed towards +y
+        let along_x = field.acceleration(Vector2::from(center), Vector2::new(1.0, 0.0));
+        assert!((along_x - Vector2::new(0.0, 0.4)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn centrifugal_pushes_away_from_the_center() {
+        let field = ForceField::RotatingFrame {
+            center: [5.0, 5.0],
+            angular_velocity: 0.2,
+        };
+        let pos = Vector2::new(8.0, 1.0);
+        let centrifugal = field.acceleration(pos, Vector2::zeros());
+        assert!((centrifugal - Vector2::new(3.0, -4.0) * 0.04).norm() < 1e-6);
+
+        let step = 1e-2;
+        let gradient = Vector2::new(
+            field.potential(pos + Vector2::new(step, 0.0))
+                - field.potential(pos - Vector2::new(step, 0.0)),
+            field.potential(pos + Vector2::new(0.0, step))
+                - field.potential(pos - Vector2::new(0.0, step)),
+        ) / (2.0 * step);
+        assert!((centrifugal + gradient).norm() < 1e-4);
+    }
+}
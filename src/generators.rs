@@ -0,0 +1,1248 @@
+use crate::config::SimConfig;
+use crate::kepler::{orbital_state, OrbitalElements};
+use crate::particle::Particle;
+use nalgebra::Vector2;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+use serde::Deserialize;
+
+pub type SimRng = ChaCha8Rng;
+
+// ChaCha8 is used instead of `StdRng`, whose algorithm may change between `rand`
+// releases, so a seed keeps giving the same particles after dependency updates
+pub fn seeded_rng(seed: u64) -> SimRng {
+    ChaCha8Rng::seed_from_u64(seed)
+}
+
+pub fn random_seed() -> u64 {
+    rand::thread_rng().gen()
+}
+
+fn random_in_circle(
+    rng: &mut impl Rng,
+    radius: f32,
+    padding: f32,
+    center: Vector2<f32>,
+) -> Vector2<f32> {
+    let angle = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
+    let distance = rng.gen_range(padding..radius);
+
+    Vector2::new(distance * angle.cos(), distance * angle.sin()) + center
+}
+
+pub fn spawn_circle(
+    particles: &mut Vec<Particle>,
+    rng: &mut impl Rng,
+    center: Vector2<f32>,
+    radius: f32,
+    particle_mass: f32,
+    particles_amount: i32,
+) {
+    for i in 0..particles_amount {
+        let pos = random_in_circle(rng, radius, 0.0, center);
+        let new_particle =
+            Particle::new(pos, Vector2::default(), particle_mass, 0.00001, i as usize);
+        particles.push(new_particle);
+    }
+}
+
+// `columns` x `rows` grid centered at `center`, every particle is moved by up to
+// `perturbation` along each axis so the lattice isn't perfectly symmetric
+pub fn spawn_lattice(
+    particles: &mut Vec<Particle>,
+    rng: &mut impl Rng,
+    center: Vector2<f32>,
+    spacing: f32,
+    (columns, rows): (u32, u32),
+    particle_mass: f32,
+    perturbation: f32,
+) {
+    let corner = center - Vector2::new((columns - 1) as f32, (rows - 1) as f32) * (spacing / 2.0);
+    for row in 0..rows {
+        for column in 0..columns {
+            let offset = if perturbation > 0.0 {
+                Vector2::new(
+                    rng.gen_range(-perturbation..perturbation),
+                    rng.gen_range(-perturbation..perturbation),
+                )
+            } else {
+                Vector2::default()
+            };
+            let pos = corner + Vector2::new(column as f32, row as f32) * spacing + offset;
+            let index = (row * columns + column) as usize;
+            particles.push(Particle::new(
+                pos,
+                Vector2::default(),
+                particle_mass,
+                0.00001,
+                index,
+            ));
+        }
+    }
+}
+
+// disk with a uniform surface density (unlike `spawn_circle`, which crowds the
+// center), at rest apart from random velocities with `perturbation` standard deviation
+pub fn spawn_uniform_disk(
+    particles: &mut Vec<Particle>,
+    rng: &mut impl Rng,
+    center: Vector2<f32>,
+    radius: f32,
+    particle_mass: f32,
+    particles_amount: i32,
+    perturbation: f32,
+) {
+    for i in 0..particles_amount {
+        let distance = radius * rng.gen_range(0.0f32..1.0).sqrt();
+        let pos = center + random_direction(rng) * distance;
+        let vel = Vector2::new(gaussian(rng), gaussian(rng)) * perturbation;
+        particles.push(Particle::new(pos, vel, particle_mass, 0.00001, i as usize));
+    }
+}
+
+// cold uniform disk rotating with `spin_parameter` times the circular velocity of
+// its enclosed mass, 1 is fully rotationally supported and 0 collapses like a disk at rest
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_rotating_disk(
+    particles: &mut Vec<Particle>,
+    rng: &mut impl Rng,
+    config: &SimConfig,
+    center: Vector2<f32>,
+    radius: f32,
+    particle_mass: f32,
+    particles_amount: i32,
+    spin_parameter: f32,
+) {
+    let start = particles.len();
+    spawn_uniform_disk(
+        particles,
+        rng,
+        center,
+        radius,
+        particle_mass,
+        particles_amount,
+        0.0,
+    );
+    assign_circular_velocities(&mut particles[start..], config, center, 0.0);
+    for particle in particles[start..].iter_mut() {
+        particle.vel *= spin_parameter;
+    }
+}
+
+// eccentricities of ring and clump orbits, e.g. `{ distribution = "rayleigh", sigma = 0.05 }`
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+#[serde(tag = "distribution", rename_all = "snake_case", deny_unknown_fields)]
+pub enum EccentricityDistribution {
+    Fixed { value: f32 },
+    Uniform { min: f32, max: f32 },
+    // the distribution of dynamically relaxed disks, most orbits close to `sigma`
+    Rayleigh { sigma: f32 },
+}
+
+impl Default for EccentricityDistribution {
+    fn default() -> Self {
+        Self::Fixed { value: 0.0 }
+    }
+}
+
+impl EccentricityDistribution {
+    // samples are clamped so every orbit stays bound
+    pub fn sample(&self, rng: &mut impl Rng) -> f32 {
+        let eccentricity = match *self {
+            Self::Fixed { value } => value,
+            Self::Uniform { min, max } if min < max => rng.gen_range(min..max),
+            Self::Uniform { min, .. } => min,
+            Self::Rayleigh { sigma } => {
+                let u: f32 = rng.gen_range(f32::EPSILON..1.0);
+                sigma * (-2.0 * u.ln()).sqrt()
+            }
+        };
+        eccentricity.clamp(0.0, 0.99)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let bound = |name: &str, value: f32| {
+            if (0.0..1.0).contains(&value) {
+                Ok(())
+            } else {
+                Err(format!("`{}` has to be in [0, 1), got {}", name, value))
+            }
+        };
+        match *self {
+            Self::Fixed { value } => bound("value", value),
+            Self::Uniform { min, max } => {
+                bound("min", min)?;
+                bound("max", max)?;
+                if min > max {
+                    return Err(format!("`min` can't be more than `max`, {} > {}", min, max));
+                }
+                Ok(())
+            }
+            Self::Rayleigh { sigma } => {
+                if sigma >= 0.0 && sigma.is_finite() {
+                    Ok(())
+                } else {
+                    Err(format!("`sigma` can't be negative, got {}", sigma))
+                }
+            }
+        }
+    }
+}
+
+// particles on Keplerian orbits around a central body, ignoring their own gravity.
+// Semi-major axes are spread evenly over the area between the radii, equal radii
+// give a thin ring. Without `central_body` the central mass has to come from
+// elsewhere, e.g. another ring around the same body
+pub struct AnnulusSpec {
+    pub center: Vector2<f32>,
+    pub initial_vel: Vector2<f32>,
+    pub inner_radius: f32,
+    pub outer_radius: f32,
+    pub central_mass: f32,
+    pub particle_mass: f32,
+    pub particles_amount: i32,
+    pub eccentricity: EccentricityDistribution,
+    pub central_body: bool,
+}
+
+impl AnnulusSpec {
+    // relative position and velocity of a random orbit
+    fn random_orbit(&self, rng: &mut impl Rng, config: &SimConfig) -> (Vector2<f32>, Vector2<f32>) {
+        let (inner, outer) = (self.inner_radius.powi(2), self.outer_radius.powi(2));
+        let semi_major_axis = if inner < outer {
+            rng.gen_range(inner..outer).sqrt()
+        } else {
+            self.inner_radius
+        };
+        let elements = OrbitalElements {
+            semi_major_axis,
+            eccentricity: self.eccentricity.sample(rng),
+            argument_of_periapsis: rng.gen_range(0.0..360.0),
+            mean_anomaly: rng.gen_range(0.0..360.0),
+        };
+        let (pos, vel) = orbital_state(&elements, (config.g * self.central_mass) as f64);
+        (pos.cast::<f32>(), vel.cast::<f32>())
+    }
+
+    fn spawn_central_body(&self, particles: &mut Vec<Particle>) {
+        if !self.central_body {
+            return;
+        }
+        let sun = Particle::new(
+            self.center,
+            self.initial_vel,
+            self.central_mass,
+            1.5,
+            self.particles_amount as usize,
+        );
+        particles.push(sun);
+    }
+}
+
+// thin ring or annulus, e.g. planetary rings, in 2D a shell is the same thing
+pub fn spawn_annulus(
+    particles: &mut Vec<Particle>,
+    rng: &mut impl Rng,
+    config: &SimConfig,
+    annulus: &AnnulusSpec,
+) {
+    for i in 0..annulus.particles_amount {
+        let (pos, vel) = annulus.random_orbit(rng, config);
+        particles.push(Particle::new(
+            annulus.center + pos,
+            annulus.initial_vel + vel,
+            annulus.particle_mass,
+            0.00001,
+            i as usize,
+        ));
+    }
+    annulus.spawn_central_body(particles);
+}
+
+// `clumps` knots of particles on random orbits of the annulus, every knot is a
+// `clump_radius` blob moving with the orbital velocity of its center
+pub fn spawn_clumps(
+    particles: &mut Vec<Particle>,
+    rng: &mut impl Rng,
+    config: &SimConfig,
+    annulus: &AnnulusSpec,
+    clumps: u32,
+    clump_radius: f32,
+) {
+    let per_clump = annulus.particles_amount / clumps as i32;
+    // the first clumps take one particle more each, so none are lost to the division
+    let remainder = annulus.particles_amount % clumps as i32;
+    let mut index = 0;
+    for clump in 0..clumps as i32 {
+        let (clump_pos, clump_vel) = annulus.random_orbit(rng, config);
+        let clump_particles = per_clump + i32::from(clump < remainder);
+        for _ in 0..clump_particles {
+            let distance = clump_radius * rng.gen_range(0.0f32..1.0).sqrt();
+            let pos = annulus.center + clump_pos + random_direction(rng) * distance;
+            particles.push(Particle::new(
+                pos,
+                annulus.initial_vel + clump_vel,
+                annulus.particle_mass,
+                0.00001,
+                index,
+            ));
+            index += 1;
+        }
+    }
+    annulus.spawn_central_body(particles);
+}
+
+// particles further than this many scale radii are resampled, the Plummer
+// profile has no edge and a few far away stragglers only slow the tree down
+const PLUMMER_MAX_RADIUS: f32 = 10.0;
+
+// total `mass` is split equally between the particles. The radial profile and the
+// speeds come from the (3D) Plummer distribution function, directions are random
+// in the plane. Since that's not an exact equilibrium of a flat cluster, velocities
+// are rescaled at the end so the cluster starts in virial equilibrium, 2K = |W|
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_plummer(
+    particles: &mut Vec<Particle>,
+    rng: &mut impl Rng,
+    config: &SimConfig,
+    center: Vector2<f32>,
+    initial_vel: Vector2<f32>,
+    scale_radius: f32,
+    mass: f32,
+    particles_amount: i32,
+) {
+    let particle_mass = mass / particles_amount as f32;
+    let mut cluster = Vec::new();
+    for i in 0..particles_amount {
+        // inverse of the enclosed mass fraction M(<r) / M = r³ / (r² + a²)^(3/2)
+        let distance = loop {
+            let mass_fraction: f32 = rng.gen_range(f32::EPSILON..1.0);
+            let distance = scale_radius / (mass_fraction.powf(-2.0 / 3.0) - 1.0).sqrt();
+            if distance < PLUMMER_MAX_RADIUS * scale_radius {
+                break distance;
+            }
+        };
+        // Aarseth, Henon & Wielen (1974) rejection sampling of q = v / v_escape
+        let q = loop {
+            let q: f32 = rng.gen_range(0.0..1.0);
+            let g: f32 = rng.gen_range(0.0..0.1);
+            if g < q * q * (1.0 - q * q).powf(3.5) {
+                break q;
+            }
+        };
+        let escape_vel = (2.0 * config.g * mass
+            / (distance * distance + scale_radius * scale_radius).sqrt())
+        .sqrt();
+
+        let pos = random_direction(rng) * distance;
+        let vel = random_direction(rng) * q * escape_vel;
+        cluster.push(Particle::new(pos, vel, particle_mass, 0.00001, i as usize));
+    }
+
+    // move into the center of mass frame, all particles weigh the same
+    let count = cluster.len().max(1) as f32;
+    let mean_pos = cluster
+        .iter()
+        .map(|particle| particle.pos)
+        .sum::<Vector2<f32>>()
+        / count;
+    let mean_vel = cluster
+        .iter()
+        .map(|particle| particle.vel)
+        .sum::<Vector2<f32>>()
+        / count;
+    for particle in cluster.iter_mut() {
+        particle.pos -= mean_pos;
+        particle.vel -= mean_vel;
+    }
+
+    let kinetic_energy: f64 = cluster
+        .iter()
+        .map(|particle| 0.5 * (particle.mass * particle.vel.norm_squared()) as f64)
+        .sum();
+    let potential_energy = direct_potential_energy(&cluster, config);
+    if kinetic_energy > 0.0 && potential_energy < 0.0 {
+        let scale = (-potential_energy / (2.0 * kinetic_energy)).sqrt() as f32;
+        for particle in cluster.iter_mut() {
+            particle.vel *= scale;
+        }
+    }
+
+    for mut particle in cluster {
+        particle.pos += center;
+        particle.vel += initial_vel;
+        particles.push(particle);
+    }
+}
+
+// disk particles further than this many scale lengths are resampled
+const DISK_MAX_RADIUS: f32 = 6.0;
+
+// disk with an exponential surface density Σ(R) ∝ exp(-R / scale_length) around an
+// optional central body. Circular velocities come from the mass enclosed by the
+// orbit (disk and central body) with the same softened force the simulation uses.
+// `toomre_q` sets the radial velocity dispersion, σ_R = Q 3.36 G Σ / κ, higher
+// values give a hotter disk that is more stable against ringing and fragmentation
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_exponential_disk(
+    particles: &mut Vec<Particle>,
+    rng: &mut impl Rng,
+    config: &SimConfig,
+    center: Vector2<f32>,
+    initial_vel: Vector2<f32>,
+    scale_length: f32,
+    disk_mass: f32,
+    central_mass: f32,
+    toomre_q: f32,
+    particles_amount: i32,
+) {
+    // mass the untruncated profile would have, so the truncated disk weighs `disk_mass`
+    let profile_mass = disk_mass / (1.0 - (1.0 + DISK_MAX_RADIUS) * (-DISK_MAX_RADIUS).exp());
+    let softening_squared = config.softening.powi(2);
+    let particle_mass = disk_mass / particles_amount as f32;
+
+    for i in 0..particles_amount {
+        let distance = exponential_disk_radius(rng, scale_length);
+        let x = distance / scale_length;
+        let enclosed_mass = central_mass + profile_mass * (1.0 - (1.0 + x) * (-x).exp());
+        // dM/dR = 2πR Σ(R)
+        let mass_derivative = profile_mass * x * (-x).exp() / scale_length;
+        let surface_density =
+            profile_mass * (-x).exp() / (2.0 * std::f32::consts::PI * scale_length.powi(2));
+
+        // the force is G M m / (R² + ε²), see `Particle::get_attraction_force`
+        let s = distance.powi(2) + softening_squared;
+        let omega_squared = config.g * enclosed_mass / (distance * s);
+        let kappa_squared = config.g
+            * (mass_derivative / s
+                + enclosed_mass * (distance.powi(2) + 3.0 * softening_squared)
+                    / (distance * s.powi(2)));
+        let circular_vel_squared = omega_squared * distance.powi(2);
+
+        let kappa = kappa_squared.sqrt();
+        let radial_dispersion = toomre_q * 3.36 * config.g * surface_density / kappa;
+        // epicyclic approximation
+        let tangential_dispersion = radial_dispersion * kappa / (2.0 * omega_squared.sqrt());
+        // asymmetric drift, a hotter disk rotates slower than the circular velocity
+        let mean_tangential_vel = (circular_vel_squared
+            + radial_dispersion.powi(2) * (1.0 - kappa_squared / (4.0 * omega_squared) - 2.0 * x))
+            .max(0.0)
+            .sqrt();
+
+        let radial_dir = random_direction(rng);
+        // same sense of rotation as `create_galaxy`
+        let tangential_dir = Vector2::new(radial_dir.y, -radial_dir.x);
+        let vel = radial_dir * gaussian(rng) * radial_dispersion
+            + tangential_dir * (mean_tangential_vel + gaussian(rng) * tangential_dispersion);
+        particles.push(Particle::new(
+            center + radial_dir * distance,
+            vel + initial_vel,
+            particle_mass,
+            0.00001,
+            i as usize,
+        ));
+    }
+
+    if central_mass > 0.0 {
+        let sun = Particle::new(
+            center,
+            initial_vel,
+            central_mass,
+            1.5,
+            particles_amount as usize,
+        );
+        particles.push(sun);
+    }
+}
+
+// radius of a particle of an exponential disk, truncated at `DISK_MAX_RADIUS`
+fn exponential_disk_radius(rng: &mut impl Rng, scale_length: f32) -> f32 {
+    // p(R) ∝ R exp(-R / Rd) is a gamma distribution with shape 2
+    loop {
+        let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+        let u2: f32 = rng.gen_range(f32::EPSILON..1.0);
+        let distance = -scale_length * (u1 * u2).ln();
+        if distance < DISK_MAX_RADIUS * scale_length {
+            return distance;
+        }
+    }
+}
+
+pub struct SpiralArms {
+    pub arms: u32,
+    // angle between an arm and a circle, in degrees
+    pub pitch_angle: f32,
+    // standard deviation of the particle offsets from the arm center line
+    pub arm_width: f32,
+    // half length of the central bar, no bar when zero
+    pub bar_length: f32,
+}
+
+// exponential disk whose particles are placed along trailing logarithmic spiral
+// arms, θ = ln(R / R0) / tan(pitch). With a bar the particles closer than
+// `bar_length` form the bar along the x axis and the arms start at its ends
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_spiral_galaxy(
+    particles: &mut Vec<Particle>,
+    rng: &mut impl Rng,
+    config: &SimConfig,
+    center: Vector2<f32>,
+    initial_vel: Vector2<f32>,
+    scale_length: f32,
+    disk_mass: f32,
+    central_mass: f32,
+    spiral: &SpiralArms,
+    particles_amount: i32,
+) {
+    let start = particles.len();
+    let particle_mass = disk_mass / particles_amount as f32;
+    let arm_start = if spiral.bar_length > 0.0 {
+        spiral.bar_length
+    } else {
+        scale_length
+    };
+    let pitch_tan = spiral.pitch_angle.to_radians().tan();
+
+    for i in 0..particles_amount {
+        let distance = exponential_disk_radius(rng, scale_length);
+        let on_arm = if distance < spiral.bar_length {
+            let side = if rng.gen_bool(0.5) { 1.0 } else { -1.0 };
+            Vector2::new(side * distance, 0.0)
+        } else {
+            let arm = rng.gen_range(0..spiral.arms);
+            let angle = (distance / arm_start).ln() / pitch_tan
+                + 2.0 * std::f32::consts::PI * arm as f32 / spiral.arms as f32;
+            Vector2::new(angle.cos(), angle.sin()) * distance
+        };
+        let offset = Vector2::new(gaussian(rng), gaussian(rng)) * spiral.arm_width;
+        particles.push(Particle::new(
+            center + on_arm + offset,
+            Vector2::default(),
+            particle_mass,
+            0.00001,
+            i as usize,
+        ));
+    }
+
+    assign_circular_velocities(&mut particles[start..], config, center, central_mass);
+    for particle in particles[start..].iter_mut() {
+        particle.vel += initial_vel;
+    }
+
+    if central_mass > 0.0 {
+        let sun = Particle::new(
+            center,
+            initial_vel,
+            central_mass,
+            1.5,
+            particles_amount as usize,
+        );
+        particles.push(sun);
+    }
+}
+
+// circular orbits around `center` from the mass of the particles closer to it plus
+// `central_mass`, in the same sense of rotation as `create_galaxy`. Only the enclosed
+// mass counts, that shell theorem holds for spherical 3D bodies but not for a flat disk
+// with a 1/r² force, where the outer mass pulls too. So the speeds are approximate,
+// closest for centrally concentrated disks and off the most for arms, bars and rings
+pub fn assign_circular_velocities(
+    particles: &mut [Particle],
+    config: &SimConfig,
+    center: Vector2<f32>,
+    central_mass: f32,
+) {
+    let mut by_distance: Vec<(f32, usize)> = particles
+        .iter()
+        .enumerate()
+        .map(|(i, particle)| (particle.pos.metric_distance(&center), i))
+        .collect();
+    by_distance.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut enclosed_mass = central_mass;
+    for (distance, i) in by_distance {
+        let particle = &mut particles[i];
+        if distance > 0.0 {
+            let radial_dir = (particle.pos - center) / distance;
+            let tangential_dir = Vector2::new(radial_dir.y, -radial_dir.x);
+            // the force is G M m / (R² + ε²), see `Particle::get_attraction_force`
+            let orbital_vel = (config.g * enclosed_mass * distance
+                / (distance.powi(2) + config.softening.powi(2)))
+            .sqrt();
+            particle.vel = tangential_dir * orbital_vel;
+        }
+        enclosed_mass += particle.mass;
+    }
+}
+
+// standard normal sample, Box-Muller transform
+pub fn gaussian(rng: &mut impl Rng) -> f32 {
+    let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+    let u2: f32 = rng.gen_range(0.0..1.0);
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+}
+
+pub fn random_direction(rng: &mut impl Rng) -> Vector2<f32> {
+    let angle = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
+    Vector2::new(angle.cos(), angle.sin())
+}
+
+// exact O(n²) sum over all pairs, only used once while generating initial conditions.
+// The rows are summed in order, a parallel reduction would change the last bits
+// of the result between runs and with them the particles of a seed
+fn direct_potential_energy(particles: &[Particle], config: &SimConfig) -> f64 {
+    let rows: Vec<f64> = particles
+        .par_iter()
+        .enumerate()
+        .map(|(i, particle)| {
+            particles[i + 1..]
+                .iter()
+                .map(|other| particle.get_potential_energy(other, config) as f64)
+                .sum::<f64>()
+        })
+        .collect();
+    rows.iter().sum()
+}
+
+// sense of rotation of a galaxy disk. Collision orbits from `create_collision` are
+// built in the prograde sense, so a prograde galaxy spins the way it orbits
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Spin {
+    #[default]
+    Prograde,
+    Retrograde,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GalaxySpec {
+    pub center: Vector2<f32>,
+    pub initial_vel: Vector2<f32>,
+    pub radius: f32,
+    pub sun_mass: f32,
+    pub particle_mass: f32,
+    pub particles_amount: i32,
+    pub spin: Spin,
+    // the sun becomes a sink swallowing everything closer than this, e.g. a black hole
+    pub accretion_radius: Option<f32>,
+}
+
+impl GalaxySpec {
+    pub fn mass(&self) -> f32 {
+        self.sun_mass + self.particle_mass * self.particles_amount as f32
+    }
+}
+
+// disk particles of `create_galaxy` start at least this far from the sun,
+// so the radius of a galaxy has to be larger
+pub const GALAXY_PADDING: f32 = 2.0;
+
+pub fn create_galaxy(
+    particles: &mut Vec<Particle>,
+    rng: &mut impl Rng,
+    config: &SimConfig,
+    galaxy: &GalaxySpec,
+) {
+    let center = galaxy.center;
+    let spin = match galaxy.spin {
+        Spin::Prograde => 1.0,
+        Spin::Retrograde => -1.0,
+    };
+    for i in 0..galaxy.particles_amount {
+        let pos = random_in_circle(rng, galaxy.radius, GALAXY_PADDING, center);
+        let distance_to_center = pos.metric_distance(&center);
+        let orbital_vel = ((config.g * galaxy.sun_mass) / distance_to_center).sqrt();
+        let dir = Vector2::new(pos.y - center.y, center.x - pos.x).normalize();
+        // the disk moves together with its sun
+        let vel = dir * orbital_vel * spin + galaxy.initial_vel;
+        let new_particle = Particle::new(pos, vel, galaxy.particle_mass, 0.00001, i as usize);
+        particles.push(new_particle);
+    }
+
+    let mut sun = Particle::new(
+        center,
+        galaxy.initial_vel,
+        galaxy.sun_mass,
+        1.5,
+        galaxy.particles_amount as usize,
+    );
+    if let Some(accretion_radius) = galaxy.accretion_radius {
+        sun = sun.with_sink(accretion_radius);
+    }
+    particles.push(sun);
+}
+
+// relative Keplerian orbit of two colliding galaxies, `eccentricity` of 1 is
+// parabolic and above 1 hyperbolic. `separation` is the initial distance
+pub struct CollisionOrbit {
+    pub pericenter: f32,
+    pub eccentricity: f32,
+    pub separation: f32,
+}
+
+// two galaxies on their relative orbit, treating each one as a point mass. They start
+// on the incoming branch with their center of mass resting at `center`, `center` and
+// `initial_vel` of the specs are replaced. Separations the orbit can't reach are
+// clamped to its apocenter (or pericenter)
+pub fn create_collision(
+    particles: &mut Vec<Particle>,
+    rng: &mut impl Rng,
+    config: &SimConfig,
+    center: Vector2<f32>,
+    galaxies: [&GalaxySpec; 2],
+    orbit: &CollisionOrbit,
+) {
+    let (first_mass, second_mass) = (galaxies[0].mass(), galaxies[1].mass());
+    let total_mass = first_mass + second_mass;
+    let mu = config.g * total_mass;
+    let eccentricity = orbit.eccentricity;
+    let semi_latus_rectum = orbit.pericenter * (1.0 + eccentricity);
+
+    // true anomaly at `separation` from r = p / (1 + e cos f), negative while approaching
+    let cos_anomaly = if eccentricity > 0.0 {
+        ((semi_latus_rectum / orbit.separation - 1.0) / eccentricity).clamp(-1.0, 1.0)
+    } else {
+        1.0
+    };
+    let anomaly = -cos_anomaly.acos();
+    let distance = semi_latus_rectum / (1.0 + eccentricity * anomaly.cos());
+    // perifocal frame with the pericenter along the x axis. The y axis is flipped so
+    // the orbit turns the same way as prograde galaxies spin
+    let relative_pos = Vector2::new(anomaly.cos(), -anomaly.sin()) * distance;
+    let relative_vel = Vector2::new(-anomaly.sin(), -(eccentricity + anomaly.cos()))
+        * (mu / semi_latus_rectum).sqrt();
+
+    let first = GalaxySpec {
+        center: center - relative_pos * (second_mass / total_mass),
+        initial_vel: -relative_vel * (second_mass / total_mass),
+        ..*galaxies[0]
+    };
+    let second = GalaxySpec {
+        center: center + relative_pos * (first_mass / total_mass),
+        initial_vel: relative_vel * (first_mass / total_mass),
+        ..*galaxies[1]
+    };
+    create_galaxy(particles, rng, config, &first);
+    create_galaxy(particles, rng, config, &second);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::Scenario;
+
+    // one of every generator, small enough to build quickly
+    const EVERY_GENERATOR: &str = "
+        [[galaxy]]
+        center = [100.0, 100.0]
+        radius = 30.0
+        sun_mass = 500.0
+        particle_mass = 0.001
+        particles_amount = 200
+
+        [[collision]]
+        center = [300.0, 100.0]
+        pericenter = 20.0
+        eccentricity = 1.0
+        separation = 80.0
+        [collision.first]
+        radius = 20.0
+        sun_mass = 500.0
+        particle_mass = 0.001
+        particles_amount = 100
+        [collision.second]
+        radius = 20.0
+        sun_mass = 500.0
+        particle_mass = 0.001
+        particles_amount = 100
+        spin = \"retrograde\"
+
+        [[disk]]
+        center = [500.0, 100.0]
+        scale_length = 10.0
+        disk_mass = 50.0
+        central_mass = 100.0
+        particles_amount = 200
+
+        [[spiral]]
+        center = [700.0, 100.0]
+        scale_length = 10.0
+        disk_mass = 50.0
+        central_mass = 100.0
+        arm_width = 2.0
+        bar_length = 8.0
+        particles_amount = 200
+
+        [[circle]]
+        center = [100.0, 300.0]
+        radius = 20.0
+        particle_mass = 0.1
+        particles_amount = 100
+
+        [[uniform_disk]]
+        center = [300.0, 300.0]
+        radius = 20.0
+        particle_mass = 0.1
+        particles_amount = 100
+        perturbation = 0.01
+
+        [[rotating_disk]]
+        center = [500.0, 300.0]
+        radius = 20.0
+        particle_mass = 0.1
+        particles_amount = 100
+        spin_parameter = 0.5
+
+        [[lattice]]
+        center = [700.0, 300.0]
+        spacing = 2.0
+        columns = 10
+        rows = 10
+        particle_mass = 0.1
+        perturbation = 0.5
+
+        [[random_field]]
+        center = [100.0, 500.0]
+        box_size = 64.0
+        grid = 16
+        particle_mass = 0.1
+        spectral_index = -1.0
+        smoothing = 2.0
+        displacement_rms = 1.0
+        hubble = 0.01
+
+        [[ring]]
+        center = [300.0, 500.0]
+        inner_radius = 10.0
+        outer_radius = 20.0
+        central_mass = 100.0
+        particle_mass = 0.001
+        particles_amount = 100
+        eccentricity = { distribution = \"rayleigh\", sigma = 0.05 }
+
+        [[clumps]]
+        center = [500.0, 500.0]
+        inner_radius = 10.0
+        outer_radius = 20.0
+        central_mass = 100.0
+        particle_mass = 0.001
+        particles_amount = 100
+        clumps = 7
+        clump_radius = 1.0
+        eccentricity = { distribution = \"uniform\", min = 0.0, max = 0.2 }
+
+        [[plummer]]
+        center = [700.0, 500.0]
+        scale_radius = 5.0
+        mass = 10.0
+        particles_amount = 200
+
+        [[system]]
+        center = [100.0, 700.0]
+        mass = 100.0
+        [[system.satellite]]
+        mass = 1.0
+        semi_major_axis = 20.0
+        eccentricity = 0.3
+        mean_anomaly = 40.0
+    ";
+
+    fn build(content: &str, seed: u64) -> Vec<Particle> {
+        let scenario = Scenario::parse(content).unwrap();
+        scenario
+            .build(&scenario.config, &mut seeded_rng(seed))
+            .unwrap()
+    }
+
+    fn bits(particle: &Particle) -> [u32; 6] {
+        [
+            particle.pos.x.to_bits(),
+            particle.pos.y.to_bits(),
+            particle.vel.x.to_bits(),
+            particle.vel.y.to_bits(),
+            particle.mass.to_bits(),
+            particle.charge.to_bits(),
+        ]
+    }
+
+    #[test]
+    fn same_seed_gives_bit_identical_particles() {
+        let first = build(EVERY_GENERATOR, 7);
+        let second = build(EVERY_GENERATOR, 7);
+        assert_eq!(first.len(), second.len());
+        for (a, b) in first.iter().zip(&second) {
+            assert_eq!(a.index, b.index);
+            assert_eq!(bits(a), bits(b), "particle {} differs", a.index);
+        }
+        let other_seed = build(EVERY_GENERATOR, 8);
+        assert!(first
+            .iter()
+            .zip(&other_seed)
+            .any(|(a, b)| bits(a) != bits(b)));
+    }
+
+    #[test]
+    fn plummer_sphere_starts_in_virial_equilibrium_at_rest() {
+        let config = SimConfig::default();
+        let center = Vector2::new(100.0, 50.0);
+        let initial_vel = Vector2::new(0.5, -0.2);
+        let mut particles = Vec::new();
+        spawn_plummer(
+            &mut particles,
+            &mut seeded_rng(11),
+            &config,
+            center,
+            initial_vel,
+            10.0,
+            50.0,
+            2000,
+        );
+        assert_eq!(particles.len(), 2000);
+
+        let momentum = particles
+            .iter()
+            .map(|particle| (particle.vel - initial_vel) * particle.mass)
+            .sum::<Vector2<f32>>();
+        let center_of_mass = particles
+            .iter()
+            .map(|particle| particle.pos * particle.mass)
+            .sum::<Vector2<f32>>()
+            / 50.0;
+        assert!(momentum.norm() < 1e-4, "P = {}", momentum);
+        assert!((center_of_mass - center).norm() < 1e-3);
+
+        let kinetic_energy: f64 = particles
+            .iter()
+            .map(|particle| {
+                0.5 * (particle.mass * (particle.vel - initial_vel).norm_squared()) as f64
+            })
+            .sum();
+        let virial_ratio = 2.0 * kinetic_energy / -direct_potential_energy(&particles, &config);
+        assert!(
+            (virial_ratio - 1.0).abs() < 1e-3,
+            "2K/|W| = {}",
+            virial_ratio
+        );
+
+        // half the mass of a Plummer sphere is within 1.305 scale radii
+        let inside = particles
+            .iter()
+            .filter(|particle| (particle.pos - center).norm() < 13.05)
+            .count();
+        assert!((inside as f32 / 2000.0 - 0.5).abs() < 0.03, "{}", inside);
+    }
+
+    #[test]
+    fn exponential_disk_has_the_requested_profile_and_toomre_q() {
+        let config = SimConfig {
+            softening: 0.0,
+            ..SimConfig::default()
+        };
+        let (scale_length, disk_mass, central_mass) = (10.0, 100.0, 20.0);
+        let amount = 40000;
+        let mut particles = Vec::new();
+        spawn_exponential_disk(
+            &mut particles,
+            &mut seeded_rng(12),
+            &config,
+            Vector2::zeros(),
+            Vector2::zeros(),
+            scale_length,
+            disk_mass,
+            central_mass,
+            1.5,
+            amount,
+        );
+        assert_eq!(particles.len(), amount as usize + 1);
+        let disk = &particles[..amount as usize];
+
+        // the mass fraction within x scale lengths of a disk truncated at `DISK_MAX_RADIUS`
+        let enclosed_fraction = |x: f32| {
+            (1.0 - (1.0 + x) * (-x).exp())
+                / (1.0 - (1.0 + DISK_MAX_RADIUS) * (-DISK_MAX_RADIUS).exp())
+        };
+        for x in [0.5, 1.0, 2.0, 4.0] {
+            let inside = disk
+                .iter()
+                .filter(|particle| particle.pos.norm() < x * scale_length)
+                .count() as f32
+                / amount as f32;
+            assert!(
+                (inside - enclosed_fraction(x)).abs() < 0.01,
+                "{} within {} scale lengths",
+                inside,
+                x
+            );
+        }
+
+        // Q = σ_R κ / (3.36 G Σ) measured in a ring around two scale lengths,
+        // κ² = R dΩ²/dR + 4Ω² from the enclosed mass
+        let (inner, outer) = (18.0f32, 22.0f32);
+        let ring: Vec<&Particle> = disk
+            .iter()
+            .filter(|particle| (inner..outer).contains(&particle.pos.norm()))
+            .collect();
+        let radial_dispersion = (ring
+            .iter()
+            .map(|particle| particle.vel.dot(&particle.pos.normalize()).powi(2))
+            .sum::<f32>()
+            / ring.len() as f32)
+            .sqrt();
+        let surface_density = ring.len() as f32 * disk_mass
+            / amount as f32
+            / (std::f32::consts::PI * (outer.powi(2) - inner.powi(2)));
+        let omega_squared = |r: f32| {
+            let mass = central_mass + disk_mass * enclosed_fraction(r / scale_length);
+            config.g * mass / r.powi(3)
+        };
+        let r = 20.0;
+        let derivative = (omega_squared(r + 0.01) - omega_squared(r - 0.01)) / 0.02;
+        let kappa = (r * derivative + 4.0 * omega_squared(r)).sqrt();
+        let toomre_q = radial_dispersion * kappa / (3.36 * config.g * surface_density);
+        assert!((toomre_q - 1.5).abs() < 0.15, "Q = {}", toomre_q);
+    }
+
+    #[test]
+    fn spiral_galaxy_has_the_requested_arms() {
+        let config = SimConfig::default();
+        let scale_length = 20.0;
+        let pitch_angle = 15.0f32;
+        let spiral = |arms, central_mass| {
+            let mut particles = Vec::new();
+            spawn_spiral_galaxy(
+                &mut particles,
+                &mut seeded_rng(13),
+                &config,
+                Vector2::zeros(),
+                Vector2::zeros(),
+                scale_length,
+                100.0,
+                central_mass,
+                &SpiralArms {
+                    arms,
+                    pitch_angle,
+                    arm_width: 0.5,
+                    bar_length: 0.0,
+                },
+                6000,
+            );
+            particles
+        };
+        assert_eq!(spiral(3, 0.0).len(), 6000);
+        let particles = spiral(3, 100.0);
+        assert_eq!(particles.len(), 6001);
+
+        // strength of the m-fold pattern along the spirals, 1 when every particle sits
+        // on one of m evenly spaced arms and close to 0 without such a pattern
+        let outer: Vec<&Particle> = particles[..6000]
+            .iter()
+            .filter(|particle| particle.pos.norm() > scale_length)
+            .collect();
+        let pattern = |m: f32| {
+            let (cos, sin) = outer.iter().fold((0.0, 0.0), |(cos, sin), particle| {
+                let r = particle.pos.norm();
+                let phase = particle.pos.y.atan2(particle.pos.x)
+                    - (r / scale_length).ln() / pitch_angle.to_radians().tan();
+                (cos + (m * phase).cos(), sin + (m * phase).sin())
+            });
+            (cos * cos + sin * sin).sqrt() / outer.len() as f32
+        };
+        assert!(pattern(3.0) > 0.8, "m = 3: {}", pattern(3.0));
+        for m in [1.0, 2.0, 4.0] {
+            assert!(pattern(m) < 0.1, "m = {}: {}", m, pattern(m));
+        }
+    }
+
+    #[test]
+    fn lattice_has_the_requested_count_and_spacing() {
+        let center = Vector2::new(50.0, 40.0);
+        let lattice = |perturbation| {
+            let mut particles = Vec::new();
+            spawn_lattice(
+                &mut particles,
+                &mut seeded_rng(14),
+                center,
+                3.0,
+                (5, 4),
+                0.1,
+                perturbation,
+            );
+            particles
+        };
+
+        let regular = lattice(0.0);
+        assert_eq!(regular.len(), 20);
+        let mean = regular
+            .iter()
+            .map(|particle| particle.pos)
+            .sum::<Vector2<f32>>()
+            / 20.0;
+        assert!((mean - center).norm() < 1e-4);
+        // particles come row by row
+        assert!((regular[1].pos - regular[0].pos - Vector2::new(3.0, 0.0)).norm() < 1e-5);
+        assert!((regular[5].pos - regular[0].pos - Vector2::new(0.0, 3.0)).norm() < 1e-5);
+        assert!((regular[19].pos - regular[0].pos - Vector2::new(12.0, 9.0)).norm() < 1e-5);
+
+        let perturbed = lattice(0.5);
+        assert_eq!(perturbed.len(), 20);
+        for (perturbed, regular) in perturbed.iter().zip(&regular) {
+            let offset = perturbed.pos - regular.pos;
+            assert!(offset.x.abs() <= 0.5 && offset.y.abs() <= 0.5);
+            assert_eq!(perturbed.index, regular.index);
+        }
+    }
+
+    fn annulus(eccentricity: EccentricityDistribution) -> AnnulusSpec {
+        AnnulusSpec {
+            center: Vector2::new(200.0, 100.0),
+            initial_vel: Vector2::new(0.3, 0.0),
+            inner_radius: 20.0,
+            outer_radius: 30.0,
+            central_mass: 500.0,
+            particle_mass: 0.001,
+            particles_amount: 1000,
+            eccentricity,
+            central_body: true,
+        }
+    }
+
+    #[test]
+    fn annulus_orbits_stay_in_the_band() {
+        let config = SimConfig::default();
+        for eccentricity in [
+            EccentricityDistribution::default(),
+            EccentricityDistribution::Uniform { min: 0.1, max: 0.3 },
+        ] {
+            let spec = annulus(eccentricity);
+            let mut particles = Vec::new();
+            spawn_annulus(&mut particles, &mut seeded_rng(15), &config, &spec);
+            assert_eq!(particles.len(), 1001);
+            let mu = config.g * spec.central_mass;
+            for particle in &particles[..1000] {
+                let pos = particle.pos - spec.center;
+                let vel = particle.vel - spec.initial_vel;
+                // vis-viva and the eccentricity vector of the orbit around the central body
+                let r = pos.norm();
+                let semi_major_axis = 1.0 / (2.0 / r - vel.norm_squared() / mu);
+                let angular_momentum = pos.x * vel.y - pos.y * vel.x;
+                let eccentricity =
+                    (Vector2::new(vel.y, -vel.x) * angular_momentum / mu - pos / r).norm();
+                let (min, max) = match spec.eccentricity {
+                    EccentricityDistribution::Uniform { min, max } => (min, max),
+                    _ => (0.0, 0.0),
+                };
+                assert!(
+                    (19.99..=30.01).contains(&semi_major_axis),
+                    "a = {}",
+                    semi_major_axis
+                );
+                assert!(
+                    (min - 1e-3..=max + 1e-3).contains(&eccentricity),
+                    "e = {}",
+                    eccentricity
+                );
+                let periapsis = semi_major_axis * (1.0 - eccentricity);
+                let apoapsis = semi_major_axis * (1.0 + eccentricity);
+                assert!(periapsis - 0.01 <= r && r <= apoapsis + 0.01);
+            }
+        }
+    }
+
+    #[test]
+    fn clumps_share_out_every_particle() {
+        let config = SimConfig::default();
+        let mut spec = annulus(EccentricityDistribution::default());
+        spec.particles_amount = 100;
+        let mut particles = Vec::new();
+        spawn_clumps(&mut particles, &mut seeded_rng(16), &config, &spec, 7, 1.0);
+        assert_eq!(particles.len(), 101);
+
+        // every clump moves with the velocity of its orbit
+        let mut sizes: Vec<usize> = Vec::new();
+        let mut velocities: Vec<Vector2<f32>> = Vec::new();
+        for particle in &particles[..100] {
+            match velocities.iter().position(|vel| *vel == particle.vel) {
+                Some(clump) => sizes[clump] += 1,
+                None => {
+                    velocities.push(particle.vel);
+                    sizes.push(1);
+                }
+            }
+        }
+        assert_eq!(sizes, vec![15, 15, 14, 14, 14, 14, 14]);
+    }
+
+    #[test]
+    fn colliding_galaxies_pass_at_the_requested_pericenter() {
+        use crate::config::Integrator;
+        use crate::simulation::Simulation;
+
+        let config = SimConfig {
+            integrator: Integrator::Leapfrog,
+            dt: 0.5,
+            ..SimConfig::default()
+        };
+        let galaxy = GalaxySpec {
+            center: Vector2::zeros(),
+            initial_vel: Vector2::zeros(),
+            radius: 20.0,
+            sun_mass: 1000.0,
+            particle_mass: 0.0001,
+            particles_amount: 200,
+            spin: Spin::Prograde,
+            accretion_radius: None,
+        };
+        let center = Vector2::new(256.0, 256.0);
+        let mut particles = Vec::new();
+        create_collision(
+            &mut particles,
+            &mut seeded_rng(17),
+            &config,
+            center,
+            [&galaxy, &galaxy],
+            &CollisionOrbit {
+                pericenter: 40.0,
+                eccentricity: 1.0,
+                separation: 160.0,
+            },
+        );
+        for (i, particle) in particles.iter_mut().enumerate() {
+            particle.index = i;
+        }
+        // center of mass and its velocity of both galaxies, disks move with their suns
+        let galaxies = |particles: &[Particle]| {
+            let mut sums = [(0.0, Vector2::zeros(), Vector2::zeros()); 2];
+            for particle in particles {
+                let sum = &mut sums[particle.index / 201];
+                sum.0 += particle.mass;
+                sum.1 += particle.pos * particle.mass;
+                sum.2 += particle.vel * particle.mass;
+            }
+            sums.map(|(mass, pos, vel)| (pos / mass, vel / mass))
+        };
+
+        let [first, second] = galaxies(&particles);
+        assert!(((second.0 - first.0).norm() - 160.0).abs() < 1e-2);
+        assert!(((first.0 + second.0) / 2.0 - center).norm() < 1e-3);
+        assert!((first.1 + second.1).norm() < 1e-5);
+        // a parabolic orbit, r = p / (1 + cos f), reaches its pericenter at p / 2
+        let (pos, vel) = (second.0 - first.0, second.1 - first.1);
+        let angular_momentum = pos.x * vel.y - pos.y * vel.x;
+        let pericenter = angular_momentum.powi(2) / (config.g * galaxy.mass() * 2.0) / 2.0;
+        assert!(
+            (pericenter - 40.0).abs() < 0.01,
+            "pericenter {}",
+            pericenter
+        );
+
+        let mut simulation = Simulation::new(particles, config, Vec::new(), Vec::new(), Vec::new());
+        let mut closest = f32::MAX;
+        for _ in 0..400 {
+            simulation.update();
+            let [first, second] = galaxies(&simulation.particles);
+            closest = closest.min((second.0 - first.0).norm());
+        }
+        assert!((closest - 40.0).abs() < 0.5, "closest approach {}", closest);
+    }
+}
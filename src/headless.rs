@@ -0,0 +1,56 @@
+use crate::simulation::Simulation;
+use crate::vtk::VtkSeries;
+use std::time::Instant;
+
+pub struct HeadlessOptions {
+    pub steps: u32,
+    pub vtk_every: Option<u32>,
+    pub log: bool,
+}
+
+// runs the simulation without opening a window, e.g. for long runs on a server
+pub fn run_headless(simulation: &mut Simulation, options: &HeadlessOptions) {
+    let mut series = match options.vtk_every {
+        Some(_) => match VtkSeries::create() {
+            Ok(series) => {
+                println!("Exporting VTK frames to {}", series.directory().display());
+                Some(series)
+            }
+            Err(exporting_err) => {
+                eprintln!("Error creating VTK folder: {}", exporting_err);
+                None
+            }
+        },
+        None => None,
+    };
+    if options.log {
+        simulation.toggle_log();
+    }
+
+    let started = Instant::now();
+    // every state from step 0 to the last one is exported at most once, the last one
+    // always, even if it's not a multiple of `vtk_every`
+    for i in 0..=options.steps {
+        if let (Some(series), Some(vtk_every)) = (series.as_mut(), options.vtk_every) {
+            if i.is_multiple_of(vtk_every) || i == options.steps {
+                if let Err(exporting_err) =
+                    series.write_frame(&simulation.particles, simulation.sim_time)
+                {
+                    eprintln!("Error exporting VTK frame: {}", exporting_err);
+                }
+            }
+        }
+        if i < options.steps {
+            simulation.update();
+        }
+    }
+
+    if simulation.log.is_some() {
+        simulation.toggle_log();
+    }
+    println!(
+        "Finished {} steps in {:.2}s",
+        options.steps,
+        started.elapsed().as_secs_f32()
+    );
+}
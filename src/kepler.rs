@@ -0,0 +1,263 @@
+use crate::config::SimConfig;
+use crate::particle::Particle;
+use nalgebra::Vector2;
+use std::f64::consts::PI;
+
+// angles are in degrees. Orbits turn the same way prograde galaxies spin, see `Spin`
+#[derive(Clone, Copy, Debug)]
+pub struct OrbitalElements {
+    pub semi_major_axis: f32,
+    pub eccentricity: f32,
+    pub argument_of_periapsis: f32,
+    pub mean_anomaly: f32,
+}
+
+pub struct Body {
+    pub mass: f32,
+    pub radius: f32,
+    // inner satellites first, every one orbits the parent together with the ones before it
+    pub satellites: Vec<Satellite>,
+}
+
+// a body, possibly with its own satellites, on an orbit around its parent
+pub struct Satellite {
+    pub orbit: OrbitalElements,
+    pub body: Body,
+}
+
+// solves Kepler's equation E - e sin E = M for the eccentric anomaly, e < 1
+pub fn eccentric_anomaly(mean_anomaly: f64, eccentricity: f64) -> f64 {
+    let mean_anomaly = mean_anomaly.rem_euclid(2.0 * PI);
+    let mut anomaly = if eccentricity < 0.8 { mean_anomaly } else { PI };
+    for _ in 0..50 {
+        let delta = (anomaly - eccentricity * anomaly.sin() - mean_anomaly)
+            / (1.0 - eccentricity * anomaly.cos());
+        anomaly -= delta;
+        if delta.abs() < 1e-14 {
+            break;
+        }
+    }
+    anomaly
+}
+
+// relative position and velocity of a bound two-body orbit, mu = G (m1 + m2)
+pub fn orbital_state(elements: &OrbitalElements, mu: f64) -> (Vector2<f64>, Vector2<f64>) {
+    let a = elements.semi_major_axis as f64;
+    let e = elements.eccentricity as f64;
+    let anomaly = eccentric_anomaly((elements.mean_anomaly as f64).to_radians(), e);
+    let (sin, cos) = anomaly.sin_cos();
+    let minor_ratio = (1.0 - e * e).sqrt();
+    let mean_motion = (mu / a.powi(3)).sqrt();
+
+    // perifocal frame, periapsis along the x axis
+    let pos = Vector2::new(a * (cos - e), a * minor_ratio * sin);
+    let vel = Vector2::new(-sin, minor_ratio * cos) * (a * mean_motion / (1.0 - e * cos));
+
+    let (sin_w, cos_w) = (elements.argument_of_periapsis as f64)
+        .to_radians()
+        .sin_cos();
+    let rotate = |v: Vector2<f64>| {
+        // the y flip makes the orbit turn the same way as `create_galaxy` disks
+        Vector2::new(v.x * cos_w - v.y * sin_w, -(v.x * sin_w + v.y * cos_w))
+    };
+    (rotate(pos), rotate(vel))
+}
+
+// the simulation softens gravity, which makes it weaker than Kepler's at distances
+// comparable to the softening. Orbits coming closer than this many softening lengths
+// to their parent don't follow their elements
+pub const MIN_PERIAPSIS_SOFTENINGS: f32 = 10.0;
+
+// closest approach of any orbit in the system, none without satellites
+pub fn closest_periapsis(body: &Body) -> Option<f32> {
+    body.satellites
+        .iter()
+        .flat_map(|satellite| {
+            let orbit = satellite.orbit;
+            let periapsis = orbit.semi_major_axis * (1.0 - orbit.eccentricity);
+            std::iter::once(periapsis).chain(closest_periapsis(&satellite.body))
+        })
+        .reduce(f32::min)
+}
+
+struct Point {
+    pos: Vector2<f64>,
+    vel: Vector2<f64>,
+    mass: f64,
+    radius: f32,
+}
+
+// the whole system in its center of mass frame
+fn build_points(body: &Body, g: f64) -> Vec<Point> {
+    let mut system = vec![Point {
+        pos: Vector2::zeros(),
+        vel: Vector2::zeros(),
+        mass: body.mass as f64,
+        radius: body.radius,
+    }];
+    for satellite in &body.satellites {
+        let subsystem = build_points(&satellite.body, g);
+        let inner_mass: f64 = system.iter().map(|point| point.mass).sum();
+        let outer_mass: f64 = subsystem.iter().map(|point| point.mass).sum();
+        let total_mass = inner_mass + outer_mass;
+        let inner_pos = system
+            .iter()
+            .map(|point| point.pos * point.mass)
+            .sum::<Vector2<f64>>()
+            / inner_mass;
+        let inner_vel = system
+            .iter()
+            .map(|point| point.vel * point.mass)
+            .sum::<Vector2<f64>>()
+            / inner_mass;
+
+        let (pos, vel) = orbital_state(&satellite.orbit, g * total_mass);
+        for point in system.iter_mut() {
+            point.pos -= inner_pos + pos * (outer_mass / total_mass);
+            point.vel -= inner_vel + vel * (outer_mass / total_mass);
+        }
+        for mut point in subsystem {
+            point.pos += pos * (inner_mass / total_mass);
+            point.vel += vel * (inner_mass / total_mass);
+            system.push(point);
+        }
+    }
+    system
+}
+
+// particles of a hierarchical system (binaries, triples, planets with moons) with its
+// center of mass at `center` moving with `initial_vel`. Positions and velocities are
+// computed in f64 so tight orbits around heavy bodies keep their precision. Velocities
+// are the ones of unsoftened gravity, so the softening has to be small compared with
+// the periapsis of every orbit, see `closest_periapsis`
+pub fn build_system(
+    body: &Body,
+    config: &SimConfig,
+    center: Vector2<f32>,
+    initial_vel: Vector2<f32>,
+) -> Vec<Particle> {
+    build_points(body, config.g as f64)
+        .into_iter()
+        .enumerate()
+        .map(|(i, point)| {
+            Particle::new(
+                point.pos.cast::<f32>() + center,
+                point.vel.cast::<f32>() + initial_vel,
+                point.mass as f32,
+                point.radius,
+                i,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn orbit(semi_major_axis: f32, eccentricity: f32) -> OrbitalElements {
+        OrbitalElements {
+            semi_major_axis,
+            eccentricity,
+            argument_of_periapsis: 30.0,
+            mean_anomaly: 70.0,
+        }
+    }
+
+    fn body(mass: f32, satellites: Vec<Satellite>) -> Body {
+        Body {
+            mass,
+            radius: 1.0,
+            satellites,
+        }
+    }
+
+    #[test]
+    fn relative_orbit_matches_the_elements() {
+        let config = SimConfig {
+            g: 1.0,
+            ..SimConfig::default()
+        };
+        let elements = orbit(10.0, 0.4);
+        let system = body(
+            50.0,
+            vec![Satellite {
+                orbit: elements,
+                body: body(5.0, Vec::new()),
+            }],
+        );
+        let particles = build_system(&system, &config, Vector2::zeros(), Vector2::zeros());
+        let pos = (particles[1].pos - particles[0].pos).cast::<f64>();
+        let vel = (particles[1].vel - particles[0].vel).cast::<f64>();
+        let mu = 55.0;
+
+        // vis-viva and the eccentricity vector, pointing at the periapsis
+        let r = pos.norm();
+        let semi_major_axis = 1.0 / (2.0 / r - vel.norm_squared() / mu);
+        let angular_momentum = pos.x * vel.y - pos.y * vel.x;
+        let eccentricity =
+            Vector2::new(vel.y * angular_momentum, -vel.x * angular_momentum) / mu - pos / r;
+        // orbits turn clockwise with the y flip, so the angle is measured the same way
+        let argument_of_periapsis = (-eccentricity.y).atan2(eccentricity.x).to_degrees();
+        let anomaly = ((1.0 - r / semi_major_axis) / eccentricity.norm()).acos();
+        let mean_anomaly = (anomaly - eccentricity.norm() * anomaly.sin()).to_degrees();
+
+        assert!(
+            (semi_major_axis - 10.0).abs() < 1e-4,
+            "a = {}",
+            semi_major_axis
+        );
+        assert!(
+            (eccentricity.norm() - 0.4).abs() < 1e-5,
+            "e = {}",
+            eccentricity.norm()
+        );
+        assert!(
+            (argument_of_periapsis - 30.0).abs() < 1e-3,
+            "ω = {}",
+            argument_of_periapsis
+        );
+        // before the apoapsis the satellite moves away from its parent
+        assert!(pos.dot(&vel) > 0.0);
+        assert!((mean_anomaly - 70.0).abs() < 1e-3, "M = {}", mean_anomaly);
+        assert!(angular_momentum < 0.0);
+    }
+
+    #[test]
+    fn system_is_built_in_its_center_of_mass_frame() {
+        let config = SimConfig::default();
+        let moon = Satellite {
+            orbit: orbit(3.0, 0.1),
+            body: body(0.1, Vec::new()),
+        };
+        let planet = Satellite {
+            orbit: orbit(60.0, 0.3),
+            body: body(2.0, vec![moon]),
+        };
+        let companion = Satellite {
+            orbit: orbit(10.0, 0.5),
+            body: body(30.0, Vec::new()),
+        };
+        let system = body(100.0, vec![companion, planet]);
+        let center = Vector2::new(200.0, 100.0);
+        let initial_vel = Vector2::new(0.5, -0.25);
+        let particles = build_system(&system, &config, center, initial_vel);
+
+        assert_eq!(particles.len(), 4);
+        let mass: f32 = particles.iter().map(|particle| particle.mass).sum();
+        let center_of_mass = particles
+            .iter()
+            .map(|particle| (particle.pos - center) * particle.mass)
+            .sum::<Vector2<f32>>()
+            / mass;
+        let momentum = particles
+            .iter()
+            .map(|particle| (particle.vel - initial_vel) * particle.mass)
+            .sum::<Vector2<f32>>();
+        assert!(center_of_mass.norm() < 1e-4, "{}", center_of_mass);
+        assert!(momentum.norm() < 1e-4, "{}", momentum);
+        // the moon, 3 (1 - 0.1)
+        let periapsis = closest_periapsis(&system).unwrap();
+        assert!((periapsis - 2.7).abs() < 1e-5, "{}", periapsis);
+    }
+}
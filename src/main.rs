@@ -1,43 +1,62 @@
+mod cli;
+mod config;
 mod consts;
+mod cosmology;
+mod diagnostics;
+mod emitters;
+mod export;
+mod forces;
+mod generators;
+mod headless;
+mod kepler;
 mod particle;
+mod potentials;
 mod quadtree;
 mod rectangle;
+mod scenario;
+mod simulation;
+mod sinks;
+mod snapshot;
+mod solar_system;
+mod sph;
 mod utils;
+mod vtk;
 
-use consts::{HEIGHT, MAX_ZOOM, WIDTH, WORLD_HEIGHT, WORLD_WIDTH};
+use cli::Args;
+use config::SimConfig;
+use emitters::Emitter;
+use export::{export_csv, export_json, import_particles};
+use forces::ForceField;
+use generators::{random_seed, seeded_rng};
 use ggez::event::{self, EventHandler};
 use ggez::graphics::{self, Color};
 use ggez::input::keyboard::{KeyCode, KeyInput};
 use ggez::GameError;
 use ggez::{conf, Context, ContextBuilder, GameResult};
+use headless::{run_headless, HeadlessOptions};
 use nalgebra::Vector2;
 use particle::Particle;
-use quadtree::QuadTree;
+use potentials::ExternalPotential;
 use rayon::prelude::*;
 use rectangle::Rectangle;
-use std::sync::{Arc, Mutex};
+use scenario::{Scenario, DEFAULT_SCENARIO};
+use simulation::Simulation;
+use snapshot::{latest_snapshot, load_snapshot, save_snapshot};
+use solar_system::check_solar_system;
+use std::path::Path;
 use std::{env, fs};
 use utils::{
-    calculate_new_position, clean_cache_images, convert_to_video, create_galaxy, create_quadtree,
-    move_on_mouse, rename_images, save_screen, screen_to_world_coords, spawn_circle, zoom_world,
+    clean_cache_images, convert_to_video, move_on_mouse, rename_images, save_screen,
+    screen_to_world_coords, timestamp, zoom_world,
 };
+use vtk::VtkSeries;
 
 fn main() {
-    let window_setup = conf::WindowSetup::default().title("Gravity Particles");
-    let window_mode = conf::WindowMode::default()
-        .dimensions(WIDTH, HEIGHT)
-        .fullscreen_type(conf::FullscreenType::Windowed)
-        .resizable(true);
-    let (mut ctx, event_loop) = ContextBuilder::new("gravity", "xanin")
-        .window_setup(window_setup)
-        .window_mode(window_mode)
-        .build()
-        .expect("aieee, could not create ggez context!");
-
-    match ctx.fs.create_dir("/image-cache") {
-        Ok(_) => println!("Created initial cache folder"),
-        Err(creating_error) => eprintln!("Error creating folder: {:?}", creating_error),
+    let args = Args::parse();
+    if args.check_solar_system {
+        std::process::exit(if check_solar_system() { 0 } else { 1 });
     }
+
     let directory_name = "results";
     let current_dir = env::current_dir().expect("Failed to get current directory");
     let new_directory_path = current_dir.join(directory_name);
@@ -53,15 +72,116 @@ fn main() {
         },
     }
 
-    let my_game = MyGame::new(&mut ctx);
+    let (particles, config, potentials, fields, emitters) = initial_conditions(&args);
+    let mut simulation = Simulation::new(particles, config, potentials, fields, emitters);
+
+    if let Some(steps) = args.headless {
+        run_headless(
+            &mut simulation,
+            &HeadlessOptions {
+                steps,
+                vtk_every: args.vtk_every,
+                log: args.log,
+            },
+        );
+        return;
+    }
+
+    let window_setup = conf::WindowSetup::default().title("Gravity Particles");
+    let window_mode = conf::WindowMode::default()
+        .dimensions(args.window_size.x, args.window_size.y)
+        .fullscreen_type(conf::FullscreenType::Windowed)
+        .resizable(true);
+    let (mut ctx, event_loop) = ContextBuilder::new("gravity", "xanin")
+        .window_setup(window_setup)
+        .window_mode(window_mode)
+        .build()
+        .expect("aieee, could not create ggez context!");
+
+    match ctx.fs.create_dir("/image-cache") {
+        Ok(_) => println!("Created initial cache folder"),
+        Err(creating_error) => eprintln!("Error creating folder: {:?}", creating_error),
+    }
+
+    let mut my_game = MyGame::new(&mut ctx, simulation, args.window_size);
+    if args.log {
+        my_game.simulation.toggle_log();
+    }
+    if args.record {
+        my_game.recording = true;
+        println!("Recording!");
+    }
 
     event::run(ctx, event_loop, my_game);
 }
 
+// particles, config, the static potentials and force fields and the emitters of a scenario
+type InitialConditions = (
+    Vec<Particle>,
+    SimConfig,
+    Vec<Box<dyn ExternalPotential>>,
+    Vec<ForceField>,
+    Vec<Emitter>,
+);
+
+fn initial_conditions(args: &Args) -> InitialConditions {
+    let input_path = args.scenario.as_deref();
+    // a .toml scenario or a .csv/.json particle file, the bundled default scenario otherwise
+    let scenario = match input_path {
+        Some(path) if path.ends_with(".toml") => Scenario::load(Path::new(path)).map(Some),
+        Some(_) => Ok(None),
+        None => Scenario::parse(DEFAULT_SCENARIO).map(Some),
+    };
+    let result = scenario.and_then(|scenario| {
+        // command line options take priority over the scenario config
+        let mut config = scenario
+            .as_ref()
+            .map(|scenario| scenario.config)
+            .unwrap_or_default();
+        args.apply(&mut config);
+        // resolved here so the seed of a random run ends up in its outputs
+        let seed = *config.seed.get_or_insert_with(random_seed);
+        println!("Seed: {}", seed);
+        config
+            .validate()
+            .map_err(|message| std::io::Error::new(std::io::ErrorKind::InvalidInput, message))?;
+
+        let (particles, potentials, fields, emitters) = match scenario {
+            Some(mut scenario) => {
+                if let Some(particles_amount) = args.particles {
+                    scenario.override_particles_amount(particles_amount);
+                }
+                let particles = scenario.build(&config, &mut seeded_rng(seed))?;
+                (
+                    particles,
+                    scenario.potentials(&config),
+                    scenario.field.clone(),
+                    scenario.emitter.clone(),
+                )
+            }
+            None => {
+                if args.particles.is_some() {
+                    println!("Particles amount can't be changed for particle files, ignoring it");
+                }
+                let particles = import_particles(Path::new(input_path.unwrap()))?;
+                (particles, Vec::new(), Vec::new(), Vec::new())
+            }
+        };
+        Ok((particles, config, potentials, fields, emitters))
+    });
+    result.unwrap_or_else(|loading_err| {
+        eprintln!(
+            "Error loading {}: {}",
+            input_path.unwrap_or("default scenario"),
+            loading_err
+        );
+        std::process::exit(1);
+    })
+}
+
 struct MyGame {
     screen: graphics::ScreenImage,
-    qt: Arc<Mutex<QuadTree>>,
-    particles: Vec<Particle>,
+    simulation: Simulation,
     keysdown: Vec<KeyCode>,
     origin: Vector2<f32>,
     zoom: f32,
@@ -70,76 +190,20 @@ struct MyGame {
     max_vel_avg: f32,
     min_vel_avg: f32,
     vel_amount: u32,
+    vtk_series: Option<VtkSeries>,
+    window_size: Vector2<f32>,
 }
 
 impl MyGame {
-    pub fn new(ctx: &mut Context) -> MyGame {
+    pub fn new(ctx: &mut Context, simulation: Simulation, window_size: Vector2<f32>) -> MyGame {
         let origin = Vector2::new(0.0, 0.0);
-        let zoom = MAX_ZOOM;
+        let zoom = window_size.x / simulation.config.world_width;
         let screen =
             graphics::ScreenImage::new(ctx, graphics::ImageFormat::Rgba8UnormSrgb, 1., 1., 1);
-        let qt = Arc::new(Mutex::new(QuadTree::new(Rectangle::new(
-            Vector2::new(0.0, 0.0),
-            WORLD_WIDTH,
-            WORLD_HEIGHT,
-        ))));
-        let mut particles: Vec<Particle> = Vec::new();
-        // create_galaxy(
-        //     &mut particles,
-        //     screen_to_world_coords(Vector2::new(410.0, 410.0), &origin, zoom),
-        //     Vector2::new(0.01, 0.0),
-        //     50.0,
-        //     1000.0,
-        //     0.0001,
-        //     5000,
-        // );
-        // create_galaxy(
-        //     &mut particles,
-        //     screen_to_world_coords(Vector2::new(WIDTH - 410.0, HEIGHT - 410.0), &origin, zoom),
-        //     Vector2::new(-0.01, 0.0),
-        //     50.0,
-        //     1000.0,
-        //     0.0001,
-        //     5000,
-        // );
-        create_galaxy(
-            &mut particles,
-            screen_to_world_coords(Vector2::new(WIDTH / 2.0, HEIGHT / 2.0), &origin, zoom),
-            Vector2::new(0.0, 0.0),
-            100.0,
-            1000.0,
-            0.0001,
-            2000,
-        );
-
-        // spawn_circle(
-        //     &mut particles,
-        //     screen_to_world_coords(Vector2::new(WIDTH / 2.0, HEIGHT / 2.0), &origin, zoom),
-        //     120.0,
-        //     3.0,
-        //     4000,
-        // );
-        // spawn_circle(
-        //     &mut particles,
-        //     screen_to_world_coords(Vector2::new(400.0, 400.0), &origin, zoom),
-        //     100.0,
-        //     3.0,
-        //     10000,
-        // );
-        // spawn_circle(
-        //     &mut particles,
-        //     screen_to_world_coords(Vector2::new(WIDTH - 400.0, HEIGHT - 400.0), &origin, zoom),
-        //     100.0,
-        //     3.0,
-        //     10000,
-        // );
-
-        particles.par_sort_by_key(|item| item.mass as u32);
 
         MyGame {
             screen,
-            qt,
-            particles,
+            simulation,
             keysdown: Vec::new(),
             origin,
             zoom,
@@ -148,23 +212,27 @@ impl MyGame {
             max_vel_avg: 0.0,
             min_vel_avg: 0.0,
             vel_amount: 0,
+            vtk_series: None,
+            window_size,
         }
     }
+
+    // zooming out further would show space outside of the world
+    fn max_zoom(&self) -> f32 {
+        self.window_size.x / self.simulation.config.world_width
+    }
 }
 
 impl EventHandler for MyGame {
     fn update(&mut self, ctx: &mut Context) -> GameResult {
-        self.qt = Arc::new(Mutex::new(create_quadtree(&self.particles)));
-        // self.particles.par_iter_mut().for_each(|particle| {
-        //     calculate_new_position(
-        //         particle,
-        //         &mut self.qt.lock().unwrap(),
-        //     );
-        // });
-        for i in 0..self.particles.len() {
-            calculate_new_position(&mut self.particles[i], &mut self.qt.lock().unwrap());
-        }
-        move_on_mouse(ctx, &mut self.origin, self.zoom);
+        self.simulation.update();
+        move_on_mouse(
+            ctx,
+            &mut self.origin,
+            self.zoom,
+            self.window_size,
+            self.simulation.config.world_size(),
+        );
         Ok(())
     }
 
@@ -173,31 +241,30 @@ impl EventHandler for MyGame {
         let mut canvas = graphics::Canvas::from_screen_image(ctx, &mut self.screen, bg_color);
         let draw_query_area = Rectangle::new(
             screen_to_world_coords(Vector2::new(0.0, 0.0), &self.origin, self.zoom),
-            WIDTH / self.zoom,
-            HEIGHT / self.zoom,
+            self.window_size.x / self.zoom,
+            self.window_size.y / self.zoom,
         );
+        // emitters and sinks change the particle count, the world can also run empty
         let max_vel = self
+            .simulation
             .particles
             .par_iter()
-            .max_by(|a, b| a.vel.norm().partial_cmp(&b.vel.norm()).unwrap())
-            .unwrap()
-            .vel
-            .norm();
+            .map(|particle| particle.vel.norm())
+            .reduce(|| 0.0, f32::max);
         let min_vel = self
+            .simulation
             .particles
             .par_iter()
-            .min_by(|a, b| a.vel.norm().partial_cmp(&b.vel.norm()).unwrap())
-            .unwrap()
-            .vel
-            .norm();
+            .map(|particle| particle.vel.norm())
+            .min_by(|a, b| a.total_cmp(b))
+            .unwrap_or(0.0);
         self.max_vel_avg =
             (self.max_vel_avg * self.vel_amount as f32 + max_vel) / (self.vel_amount as f32 + 1.0);
         self.min_vel_avg =
             (self.min_vel_avg * self.vel_amount as f32 + min_vel) / (self.vel_amount as f32 + 1.0);
         self.vel_amount += 1;
-        let locked_qt = self.qt.lock().unwrap();
-        let particles_to_draw = locked_qt.query(&draw_query_area);
-        locked_qt.show(
+        let particles_to_draw = self.simulation.qt.query(&draw_query_area);
+        self.simulation.qt.show(
             &mut canvas,
             ctx,
             self.origin,
@@ -215,8 +282,20 @@ impl EventHandler for MyGame {
             save_screen(ctx, &mut self.screen, self.frame_count);
             recording_title = "Recording..."
         }
-        ctx.gfx
-            .set_window_title(format!("FPS: {} {}", fps.as_str(), recording_title).as_str());
+        let drift = self
+            .simulation
+            .diagnostics
+            .energy_drift(&self.simulation.initial_diagnostics);
+        ctx.gfx.set_window_title(
+            format!(
+                "FPS: {} N: {} dE/E: {:.2e} {}",
+                fps.as_str(),
+                self.simulation.particles.len(),
+                drift,
+                recording_title
+            )
+            .as_str(),
+        );
         canvas.finish(ctx)?;
         ctx.gfx.present(&self.screen.image(ctx))?;
         Ok(())
@@ -243,6 +322,59 @@ impl EventHandler for MyGame {
                 clean_cache_images(ctx);
                 println!("Saved!");
             }
+            if keycode == KeyCode::E {
+                let path = format!("results/{}_particles", timestamp());
+                let csv_path = format!("{}.csv", path);
+                let json_path = format!("{}.json", path);
+                let particles = &self.simulation.particles;
+                let config = &self.simulation.config;
+                match export_csv(Path::new(&csv_path), particles, config)
+                    .and_then(|_| export_json(Path::new(&json_path), particles, config))
+                {
+                    Ok(_) => println!("Exported particles to {} and {}", csv_path, json_path),
+                    Err(exporting_err) => eprintln!("Error exporting particles: {}", exporting_err),
+                }
+            }
+            if keycode == KeyCode::V {
+                if self.vtk_series.is_none() {
+                    match VtkSeries::create() {
+                        Ok(series) => self.vtk_series = Some(series),
+                        Err(exporting_err) => {
+                            eprintln!("Error creating VTK folder: {}", exporting_err)
+                        }
+                    }
+                }
+                if let Some(series) = self.vtk_series.as_mut() {
+                    match series.write_frame(&self.simulation.particles, self.simulation.sim_time) {
+                        Ok(path) => println!("Exported VTK frame to {}", path.display()),
+                        Err(exporting_err) => {
+                            eprintln!("Error exporting VTK frame: {}", exporting_err)
+                        }
+                    }
+                }
+            }
+            if keycode == KeyCode::F5 {
+                let path = format!("results/{}.snap", timestamp());
+                match save_snapshot(Path::new(&path), &self.simulation.snapshot()) {
+                    Ok(_) => println!("Saved snapshot to {}", path),
+                    Err(saving_err) => eprintln!("Error saving snapshot: {}", saving_err),
+                }
+            }
+            if keycode == KeyCode::F9 {
+                match latest_snapshot(Path::new("results")) {
+                    Some(path) => match load_snapshot(&path) {
+                        Ok(snapshot) => {
+                            self.simulation.restore(snapshot);
+                            println!("Loaded snapshot {}", path.display());
+                        }
+                        Err(loading_err) => eprintln!("Error loading snapshot: {}", loading_err),
+                    },
+                    None => println!("No snapshots found in results folder"),
+                }
+            }
+            if keycode == KeyCode::L {
+                self.simulation.toggle_log();
+            }
         }
         Ok(())
     }
@@ -255,7 +387,8 @@ impl EventHandler for MyGame {
     }
 
     fn mouse_wheel_event(&mut self, ctx: &mut Context, _x: f32, y: f32) -> Result<(), GameError> {
-        zoom_world(ctx, &mut self.origin, &mut self.zoom, y);
+        let max_zoom = self.max_zoom();
+        zoom_world(ctx, &mut self.origin, &mut self.zoom, y, max_zoom);
 
         Ok(())
     }
@@ -6,7 +6,41 @@ use ggez::{
 };
 use nalgebra::Vector2;
 
-use crate::consts::{G, SOFTENING};
+use crate::config::SimConfig;
+
+// gas particles are smoothed over their neighbours with SPH on top of gravity,
+// see `sph.rs`. Everything that isn't derived from the neighbours is kept here
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GasState {
+    // specific internal energy u, the temperature of the gas
+    pub internal_energy: f32,
+    // the kernel reaches out to twice this distance
+    pub smoothing_length: f32,
+    pub density: f32,
+    pub pressure: f32,
+    pub sound_speed: f32,
+    // pressure and viscosity acceleration and du/dt of the last force evaluation
+    pub acceleration: Vector2<f32>,
+    pub energy_rate: f32,
+}
+
+// a black hole or a protostar, see `sinks.rs`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SinkState {
+    // every other particle coming closer than this is swallowed
+    pub accretion_radius: f32,
+    // total mass swallowed so far
+    pub accreted_mass: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ParticleKind {
+    // stars and dark matter, only gravity and the other forces act on them
+    #[default]
+    Collisionless,
+    Gas(GasState),
+    Sink(SinkState),
+}
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Particle {
@@ -15,6 +49,9 @@ pub struct Particle {
     pub net_force: Vector2<f32>,
     pub mass: f32,
     pub radius: f32,
+    // zero for neutral particles, which skip the Coulomb forces
+    pub charge: f32,
+    pub kind: ParticleKind,
     pub index: usize,
 }
 
@@ -26,20 +63,106 @@ impl Particle {
             net_force: Vector2::new(0.0, 0.0),
             mass,
             radius,
+            charge: 0.0,
+            kind: ParticleKind::Collisionless,
             index,
         }
     }
 
-    pub fn get_attraction_force(&mut self, another_particle: &Particle) -> Vector2<f32> {
-        let r =
-            (self.pos.metric_distance(&another_particle.pos).powi(2) + SOFTENING.powi(2)).sqrt();
+    // turns the particle into gas, density and pressure are filled in by the first SPH pass
+    pub fn with_gas(mut self, internal_energy: f32, smoothing_length: f32) -> Self {
+        self.kind = ParticleKind::Gas(GasState {
+            internal_energy,
+            smoothing_length,
+            density: 0.0,
+            pressure: 0.0,
+            sound_speed: 0.0,
+            acceleration: Vector2::new(0.0, 0.0),
+            energy_rate: 0.0,
+        });
+        self
+    }
+
+    pub fn with_sink(mut self, accretion_radius: f32) -> Self {
+        self.kind = ParticleKind::Sink(SinkState {
+            accretion_radius,
+            accreted_mass: 0.0,
+        });
+        self
+    }
+
+    pub fn gas(&self) -> Option<&GasState> {
+        match &self.kind {
+            ParticleKind::Gas(gas) => Some(gas),
+            _ => None,
+        }
+    }
+
+    pub fn sink(&self) -> Option<&SinkState> {
+        match &self.kind {
+            ParticleKind::Sink(sink) => Some(sink),
+            _ => None,
+        }
+    }
+
+    pub fn with_charge(mut self, charge: f32) -> Self {
+        self.charge = charge;
+        self
+    }
+
+    pub fn get_attraction_force(
+        &self,
+        another_particle: &Particle,
+        config: &SimConfig,
+    ) -> Vector2<f32> {
+        let r = (self.pos.metric_distance(&another_particle.pos).powi(2)
+            + config.softening.powi(2))
+        .sqrt();
+        // coincident particles have no direction to pull in, like the electric force
+        if another_particle.pos == self.pos {
+            return Vector2::new(0.0, 0.0);
+        }
         let dir = (another_particle.pos - self.pos).normalize();
-        let magnitude = G * ((self.mass * another_particle.mass) / r.powi(2));
+        let magnitude = config.g * ((self.mass * another_particle.mass) / r.powi(2));
         let force = dir * magnitude;
 
         force
     }
 
+    pub fn get_potential_energy(&self, another_particle: &Particle, config: &SimConfig) -> f32 {
+        let r = (self.pos.metric_distance(&another_particle.pos).powi(2)
+            + config.softening.powi(2))
+        .sqrt();
+        -config.g * (self.mass * another_particle.mass) / r
+    }
+
+    // Coulomb force of a point `charge` at `pos`, softened like gravity.
+    // Like charges repel, so it points away from `pos` when the product is positive
+    pub fn get_electric_force(
+        &self,
+        pos: Vector2<f32>,
+        charge: f32,
+        config: &SimConfig,
+    ) -> Vector2<f32> {
+        let offset = self.pos - pos;
+        let distance = offset.norm();
+        if distance == 0.0 {
+            return Vector2::new(0.0, 0.0);
+        }
+        let r_squared = distance.powi(2) + config.softening.powi(2);
+        offset / distance * (config.coulomb * self.charge * charge / r_squared)
+    }
+
+    pub fn get_electric_potential_energy(
+        &self,
+        pos: Vector2<f32>,
+        charge: f32,
+        config: &SimConfig,
+    ) -> f32 {
+        let r = ((self.pos - pos).norm_squared() + config.softening.powi(2)).sqrt();
+        config.coulomb * self.charge * charge / r
+    }
+
     pub fn get_distance_to(&self, object: &Vector2<f32>) -> f32 {
         f32::hypot(object.x - self.pos.x, object.y - self.pos.y)
     }
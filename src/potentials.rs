@@ -0,0 +1,228 @@
+use crate::config::SimConfig;
+use nalgebra::Vector2;
+
+// fixed background potential acting on every particle, e.g. a dark matter halo
+// that would be too expensive to simulate with particles
+pub trait ExternalPotential: Send + Sync {
+    // acceleration of a particle at `pos`
+    fn acceleration(&self, pos: Vector2<f32>, config: &SimConfig) -> Vector2<f32>;
+    // potential energy per unit mass at `pos`
+    fn potential(&self, pos: Vector2<f32>, config: &SimConfig) -> f32;
+}
+
+// acceleration pointing to `center` with magnitude `magnitude(r)`,
+// zero at the center itself where the direction is undefined
+fn radial(pos: Vector2<f32>, center: Vector2<f32>, magnitude: impl Fn(f32) -> f32) -> Vector2<f32> {
+    let offset = center - pos;
+    let distance = offset.norm();
+    if distance == 0.0 {
+        return Vector2::default();
+    }
+    offset / distance * magnitude(distance)
+}
+
+// softened the same way as particles, see `Particle::get_attraction_force`
+pub struct PointMass {
+    pub center: Vector2<f32>,
+    pub mass: f32,
+}
+
+impl ExternalPotential for PointMass {
+    fn acceleration(&self, pos: Vector2<f32>, config: &SimConfig) -> Vector2<f32> {
+        radial(pos, self.center, |r| {
+            config.g * self.mass / (r * r + config.softening.powi(2))
+        })
+    }
+
+    // the potential of that force, -G M (π/2 - atan(r / ε)) / ε, which is -G M / r without
+    // softening. The Plummer potential of particle pairs would pull a little differently
+    fn potential(&self, pos: Vector2<f32>, config: &SimConfig) -> f32 {
+        let r = (pos - self.center).norm();
+        if config.softening == 0.0 {
+            return -config.g * self.mass / r;
+        }
+        let angle = std::f32::consts::FRAC_PI_2 - (r / config.softening).atan();
+        -config.g * self.mass * angle / config.softening
+    }
+}
+
+// Φ = -G M / (r + a), a bulge or a halo with a finite total mass
+pub struct Hernquist {
+    pub center: Vector2<f32>,
+    pub mass: f32,
+    pub scale_radius: f32,
+}
+
+impl ExternalPotential for Hernquist {
+    fn acceleration(&self, pos: Vector2<f32>, config: &SimConfig) -> Vector2<f32> {
+        radial(pos, self.center, |r| {
+            config.g * self.mass / (r + self.scale_radius).powi(2)
+        })
+    }
+
+    fn potential(&self, pos: Vector2<f32>, config: &SimConfig) -> f32 {
+        let r = (pos - self.center).norm();
+        -config.g * self.mass / (r + self.scale_radius)
+    }
+}
+
+// Navarro-Frenk-White dark matter halo, Φ = -G M ln(1 + r / rs) / r.
+// `mass` is the characteristic mass 4π ρ0 rs³, the halo mass grows without a bound
+pub struct Nfw {
+    pub center: Vector2<f32>,
+    pub mass: f32,
+    pub scale_radius: f32,
+}
+
+impl ExternalPotential for Nfw {
+    fn acceleration(&self, pos: Vector2<f32>, config: &SimConfig) -> Vector2<f32> {
+        radial(pos, self.center, |r| {
+            let x = r / self.scale_radius;
+            let enclosed_mass = self.mass * ((1.0 + x).ln() - x / (1.0 + x));
+            config.g * enclosed_mass / (r * r)
+        })
+    }
+
+    fn potential(&self, pos: Vector2<f32>, config: &SimConfig) -> f32 {
+        let r = (pos - self.center).norm();
+        if r == 0.0 {
+            // the limit of ln(1 + x) / x
+            return -config.g * self.mass / self.scale_radius;
+        }
+        -config.g * self.mass * (1.0 + r / self.scale_radius).ln() / r
+    }
+}
+
+// Φ = v0² ln(r² + rc²) / 2, a flat rotation curve with circular velocity `circular_vel`
+// far from the core. A zero `core_radius` is the singular isothermal sphere
+pub struct Logarithmic {
+    pub center: Vector2<f32>,
+    pub circular_vel: f32,
+    pub core_radius: f32,
+}
+
+impl ExternalPotential for Logarithmic {
+    fn acceleration(&self, pos: Vector2<f32>, _config: &SimConfig) -> Vector2<f32> {
+        radial(pos, self.center, |r| {
+            self.circular_vel.powi(2) * r / (r * r + self.core_radius.powi(2))
+        })
+    }
+
+    fn potential(&self, pos: Vector2<f32>, _config: &SimConfig) -> f32 {
+        let r_squared = (pos - self.center).norm_squared() + self.core_radius.powi(2);
+        // the singular potential diverges at the center, treat it as the bottom
+        0.5 * self.circular_vel.powi(2) * r_squared.max(f32::MIN_POSITIVE).ln()
+    }
+}
+
+// Φ = ω² r² / 2, every particle oscillates around `center` with the angular `frequency`.
+// The time averaged pseudo-potential of an ion trap, it confines charges of any sign
+pub struct Harmonic {
+    pub center: Vector2<f32>,
+    pub frequency: f32,
+}
+
+impl ExternalPotential for Harmonic {
+    fn acceleration(&self, pos: Vector2<f32>, _config: &SimConfig) -> Vector2<f32> {
+        (self.center - pos) * self.frequency.powi(2)
+    }
+
+    fn potential(&self, pos: Vector2<f32>, _config: &SimConfig) -> f32 {
+        0.5 * self.frequency.powi(2) * (pos - self.center).norm_squared()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn every_potential() -> Vec<(&'static str, Box<dyn ExternalPotential>)> {
+        let center = Vector2::new(1.0, -2.0);
+        vec![
+            ("point mass", Box::new(PointMass { center, mass: 50.0 })),
+            (
+                "hernquist",
+                Box::new(Hernquist {
+                    center,
+                    mass: 50.0,
+                    scale_radius: 3.0,
+                }),
+            ),
+            (
+                "nfw",
+                Box::new(Nfw {
+                    center,
+                    mass: 50.0,
+                    scale_radius: 3.0,
+                }),
+            ),
+            (
+                "logarithmic",
+                Box::new(Logarithmic {
+                    center,
+                    circular_vel: 0.7,
+                    core_radius: 2.0,
+                }),
+            ),
+            (
+                "harmonic",
+                Box::new(Harmonic {
+                    center,
+                    frequency: 0.3,
+                }),
+            ),
+        ]
+    }
+
+    #[test]
+    fn acceleration_is_minus_the_gradient_of_the_potential() {
+        let config = SimConfig::default();
+        let step = 1e-2;
+        for (name, potential) in every_potential() {
+            for pos in [
+                Vector2::new(2.0, -1.5),
+                Vector2::new(-4.0, 3.0),
+                Vector2::new(9.0, 7.0),
+            ] {
+                let derivative = |offset: Vector2<f32>| {
+                    (potential.potential(pos + offset, &config)
+                        - potential.potential(pos - offset, &config))
+                        / (2.0 * step)
+                };
+                let gradient = Vector2::new(
+                    derivative(Vector2::new(step, 0.0)),
+                    derivative(Vector2::new(0.0, step)),
+                );
+                let acceleration = potential.acceleration(pos, &config);
+                assert!(
+                    (acceleration + gradient).norm() < 1e-3 * acceleration.norm(),
+                    "{} at {}: acceleration {}, gradient {}",
+                    name,
+                    pos,
+                    acceleration,
+                    gradient
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn logarithmic_rotation_curve_is_flat() {
+        let config = SimConfig::default();
+        let potential = Logarithmic {
+            center: Vector2::new(0.0, 0.0),
+            circular_vel: 0.7,
+            core_radius: 1.0,
+        };
+        for r in [20.0, 50.0, 100.0, 200.0] {
+            let acceleration = potential.acceleration(Vector2::new(r, 0.0), &config);
+            let circular_vel = (acceleration.norm() * r).sqrt();
+            assert!(
+                (circular_vel - 0.7).abs() < 0.7 * 2e-3,
+                "v = {} at r = {}",
+                circular_vel,
+                r
+            );
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::config::SimConfig;
 use crate::particle::Particle;
 use crate::rectangle::Rectangle;
 use ggez::{
@@ -7,27 +8,48 @@ use ggez::{
 use nalgebra::Vector2;
 // use rayon::prelude::*;
 
+// nodes this deep stop subdividing and keep every particle that ends up in them,
+// f32 positions can't be split much further and coincident particles never would be
+const MAX_LEVEL: u32 = 20;
+
 #[derive(Clone)]
 pub struct QuadTree {
     bounds: Rectangle,
     children: Option<[Box<QuadTree>; 4]>,
-    particle: Option<Particle>,
+    // only leaves hold particles, usually one, more at `MAX_LEVEL`
+    particles: Vec<Particle>,
+    level: u32,
     mass: f32,
     m_center_pos: Vector2<f32>,
+    // positive and negative charges are kept apart, a neutral but polarized
+    // node would have no field with only its net charge
+    positive_charge: f32,
+    positive_center: Vector2<f32>,
+    negative_charge: f32,
+    negative_center: Vector2<f32>,
+    // largest smoothing length of the gas below, zero without gas
+    max_smoothing_length: f32,
 }
 
 impl QuadTree {
     pub fn new(bounds: Rectangle) -> Self {
         let copy_bounds = bounds.clone();
+        let center = Vector2::new(
+            copy_bounds.top_left_pos.x + copy_bounds.w / 2.0,
+            copy_bounds.top_left_pos.y + copy_bounds.h / 2.0,
+        );
         Self {
             bounds,
             children: None,
-            particle: None,
+            particles: Vec::new(),
+            level: 0,
             mass: 0.0,
-            m_center_pos: Vector2::new(
-                copy_bounds.top_left_pos.x + copy_bounds.w / 2.0,
-                copy_bounds.top_left_pos.y + copy_bounds.h / 2.0,
-            ),
+            m_center_pos: center,
+            positive_charge: 0.0,
+            positive_center: center,
+            negative_charge: 0.0,
+            negative_center: center,
+            max_smoothing_length: 0.0,
         }
     }
 
@@ -42,12 +64,16 @@ impl QuadTree {
         let topright = Rectangle::new(Vector2::new(x + w / 2.0, y), w / 2.0, h / 2.0);
         let bottomleft = Rectangle::new(Vector2::new(x, y + h / 2.0), w / 2.0, h / 2.0);
         let bottomright = Rectangle::new(Vector2::new(x + w / 2.0, y + h / 2.0), w / 2.0, h / 2.0);
-        self.children = Some([
+        let mut children = [
             Box::new(QuadTree::new(topleft)),
             Box::new(QuadTree::new(topright)),
             Box::new(QuadTree::new(bottomleft)),
             Box::new(QuadTree::new(bottomright)),
-        ]);
+        ];
+        for leaf in children.iter_mut() {
+            leaf.level = self.level + 1;
+        }
+        self.children = Some(children);
     }
 
     pub fn insert(&mut self, particle: &Particle) {
@@ -55,80 +81,235 @@ impl QuadTree {
             return;
         }
 
-        if self.particle.is_none() {
-            self.particle = Some(*particle);
-        } else {
-            if !self.is_divided() {
-                self.subdivide();
+        if !self.is_divided() {
+            if self.particles.is_empty() || self.level >= MAX_LEVEL {
+                self.particles.push(*particle);
+                self.update_mass();
+                return;
             }
-            // self.children
-            //     .as_mut()
-            //     .unwrap()
-            //     .par_iter_mut()
-            //     .for_each(|leaf| {
-            //         leaf.as_mut().insert(particle);
-            //     });
-            for leaf in self.children.as_mut().unwrap().as_mut() {
-                leaf.as_mut().insert(particle);
+            // the particle held so far moves down too, so every particle sits in a leaf
+            // and counts towards the mass and charge of all the nodes above it
+            self.subdivide();
+            for held in std::mem::take(&mut self.particles) {
+                self.insert_into_children(&held);
             }
-            self.update_mass();
         }
+        self.insert_into_children(particle);
+        self.update_mass();
     }
 
-    pub fn calculate_force(&mut self, particle: &mut Particle) {
+    fn insert_into_children(&mut self, particle: &Particle) {
+        // self.children
+        //     .as_mut()
+        //     .unwrap()
+        //     .par_iter_mut()
+        //     .for_each(|leaf| {
+        //         leaf.as_mut().insert(particle);
+        //     });
+        for leaf in self.children.as_mut().unwrap().as_mut() {
+            leaf.as_mut().insert(particle);
+        }
+    }
+
+    pub fn calculate_force(&self, particle: &mut Particle, config: &SimConfig) {
         if !self.is_divided() {
-            if let Some(existent_particle) = &self.particle {
+            for existent_particle in &self.particles {
                 if existent_particle.index != particle.index {
-                    let attraction_force =
-                        particle.get_attraction_force(&self.particle.as_ref().unwrap());
+                    let attraction_force = particle.get_attraction_force(existent_particle, config);
                     // let attraction_force = self.particle.unwrap().get_attraction_force(particle);
                     particle.net_force += attraction_force;
+                    if particle.charge != 0.0 && existent_particle.charge != 0.0 {
+                        particle.net_force += particle.get_electric_force(
+                            existent_particle.pos,
+                            existent_particle.charge,
+                            config,
+                        );
+                    }
                 }
             }
             return;
         }
 
         let ratio = self.bounds.w / particle.get_distance_to(&self.m_center_pos);
-        if ratio < 0.5 {
-            let attraction_force = particle.get_attraction_force(&Particle::new(
-                self.m_center_pos,
-                Vector2::new(0.0, 0.0),
-                self.mass,
-                1.0,
-                1000000,
-            ));
+        if ratio < config.theta {
+            let attraction_force = particle.get_attraction_force(
+                &Particle::new(
+                    self.m_center_pos,
+                    Vector2::new(0.0, 0.0),
+                    self.mass,
+                    1.0,
+                    1000000,
+                ),
+                config,
+            );
             particle.net_force += attraction_force;
+            if particle.charge != 0.0 {
+                for (charge, center) in self.charges() {
+                    particle.net_force += particle.get_electric_force(center, charge, config);
+                }
+            }
             return;
         }
 
-        for leaf in self.children.as_mut().unwrap() {
-            leaf.calculate_force(particle);
+        for leaf in self.children.as_ref().unwrap() {
+            leaf.calculate_force(particle, config);
         }
     }
 
-    fn update_mass(&mut self) {
+    pub fn calculate_potential(&self, particle: &Particle, config: &SimConfig) -> f32 {
         if !self.is_divided() {
-            if self.particle.is_none() {
-                return;
-            }
-            self.mass = self.particle.as_ref().unwrap().mass;
-            self.m_center_pos = self.particle.as_ref().unwrap().pos;
-            return;
+            return self
+                .particles
+                .iter()
+                .filter(|existent_particle| existent_particle.index != particle.index)
+                .map(|existent_particle| {
+                    particle.get_potential_energy(existent_particle, config)
+                        + particle.get_electric_potential_energy(
+                            existent_particle.pos,
+                            existent_particle.charge,
+                            config,
+                        )
+                })
+                .sum();
+        }
+
+        let ratio = self.bounds.w / particle.get_distance_to(&self.m_center_pos);
+        if ratio < config.theta {
+            let electric_energy: f32 = self
+                .charges()
+                .map(|(charge, center)| {
+                    particle.get_electric_potential_energy(center, charge, config)
+                })
+                .sum();
+            return particle.get_potential_energy(
+                &Particle::new(
+                    self.m_center_pos,
+                    Vector2::new(0.0, 0.0),
+                    self.mass,
+                    1.0,
+                    1000000,
+                ),
+                config,
+            ) + electric_energy;
         }
+
+        self.children
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|leaf| leaf.calculate_potential(particle, config))
+            .sum()
+    }
+
+    // children are up to date when this runs, every insert updates the nodes on its way back up
+    fn update_mass(&mut self) {
         let mut mass_sum: f32 = 0.0;
-        let mut center_x: f32 = 0.0;
-        let mut center_y: f32 = 0.0;
+        let mut center = Vector2::new(0.0, 0.0);
+        let (mut positive_charge, mut positive_center) = (0.0, Vector2::new(0.0, 0.0));
+        let (mut negative_charge, mut negative_center) = (0.0, Vector2::new(0.0, 0.0));
+        let mut max_smoothing_length: f32 = 0.0;
 
-        for leaf in self.children.as_mut().unwrap() {
-            leaf.as_mut().update_mass();
-            mass_sum += leaf.as_ref().mass;
-            center_x += leaf.as_ref().m_center_pos.x * leaf.as_ref().mass;
-            center_y += leaf.as_ref().m_center_pos.y * leaf.as_ref().mass;
+        match self.children.as_ref() {
+            Some(children) => {
+                for leaf in children {
+                    mass_sum += leaf.mass;
+                    center += leaf.m_center_pos * leaf.mass;
+                    positive_charge += leaf.positive_charge;
+                    positive_center += leaf.positive_center * leaf.positive_charge;
+                    negative_charge += leaf.negative_charge;
+                    negative_center += leaf.negative_center * leaf.negative_charge;
+                    max_smoothing_length = leaf.max_smoothing_length.max(max_smoothing_length);
+                }
+            }
+            None => {
+                for particle in &self.particles {
+                    mass_sum += particle.mass;
+                    center += particle.pos * particle.mass;
+                    if particle.charge > 0.0 {
+                        positive_charge += particle.charge;
+                        positive_center += particle.pos * particle.charge;
+                    } else {
+                        negative_charge += particle.charge;
+                        negative_center += particle.pos * particle.charge;
+                    }
+                    let smoothing_length = particle.gas().map_or(0.0, |gas| gas.smoothing_length);
+                    max_smoothing_length = smoothing_length.max(max_smoothing_length);
+                }
+            }
         }
+        self.max_smoothing_length = max_smoothing_length;
         self.mass = mass_sum;
-        center_x /= mass_sum;
-        center_y /= mass_sum;
-        self.m_center_pos = Vector2::new(center_x, center_y);
+        if mass_sum > 0.0 {
+            self.m_center_pos = center / mass_sum;
+        }
+        // the centers of charge are weighted like the center of mass,
+        // a node without charges of one sign keeps it at the center of mass
+        self.positive_charge = positive_charge;
+        self.positive_center = if positive_charge != 0.0 {
+            positive_center / positive_charge
+        } else {
+            self.m_center_pos
+        };
+        self.negative_charge = negative_charge;
+        self.negative_center = if negative_charge != 0.0 {
+            negative_center / negative_charge
+        } else {
+            self.m_center_pos
+        };
+    }
+
+    // the aggregated charges of the node as up to two point charges
+    fn charges(&self) -> impl Iterator<Item = (f32, Vector2<f32>)> {
+        [
+            (self.positive_charge, self.positive_center),
+            (self.negative_charge, self.negative_center),
+        ]
+        .into_iter()
+        .filter(|(charge, _)| *charge != 0.0)
+    }
+
+    // gas particles closer to `pos` than twice the larger of `smoothing_length` and
+    // their own smoothing length, everything an SPH kernel at `pos` can overlap with
+    pub fn gas_neighbours(
+        &self,
+        pos: Vector2<f32>,
+        smoothing_length: f32,
+        neighbours: &mut Vec<Particle>,
+    ) {
+        if self.particles.is_empty() && !self.is_divided() {
+            return;
+        }
+        let reach = 2.0 * smoothing_length.max(self.max_smoothing_length);
+        let top_left = self.bounds.top_left_pos;
+        let bottom_right = top_left + Vector2::new(self.bounds.w, self.bounds.h);
+        let outside = Vector2::new(
+            (top_left.x - pos.x).max(pos.x - bottom_right.x).max(0.0),
+            (top_left.y - pos.y).max(pos.y - bottom_right.y).max(0.0),
+        );
+        if outside.norm() >= reach {
+            return;
+        }
+
+        for particle in &self.particles {
+            if let Some(gas) = particle.gas() {
+                let reach = 2.0 * smoothing_length.max(gas.smoothing_length);
+                if (particle.pos - pos).norm() < reach {
+                    neighbours.push(*particle);
+                }
+            }
+        }
+        if let Some(children) = self.children.as_ref() {
+            for leaf in children {
+                leaf.gas_neighbours(pos, smoothing_length, neighbours);
+            }
+        }
+    }
+
+    pub fn depth(&self) -> u32 {
+        match self.children.as_ref() {
+            Some(children) => 1 + children.iter().map(|leaf| leaf.depth()).max().unwrap_or(0),
+            None => 1,
+        }
     }
 
     pub fn show(
@@ -169,13 +350,10 @@ impl QuadTree {
             None => {}
         }
 
-        match &self.particle {
-            Some(existent_particle) => {
-                if particles_to_draw.contains(&existent_particle) {
-                    existent_particle.show(canvas, ctx, offset, zoom, max_vel, min_vel);
-                }
+        for existent_particle in &self.particles {
+            if particles_to_draw.contains(existent_particle) {
+                existent_particle.show(canvas, ctx, offset, zoom, max_vel, min_vel);
             }
-            None => {}
         }
     }
 
@@ -185,7 +363,7 @@ impl QuadTree {
             return results;
         }
 
-        if let Some(particle) = &self.particle {
+        for particle in &self.particles {
             if rect.contains(particle) {
                 results.push(*particle);
             }
@@ -199,3 +377,83 @@ impl QuadTree {
         results
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_quadtree;
+
+    fn net_forces(particles: &mut [Particle], config: &SimConfig) {
+        let qt = create_quadtree(particles, config.world_size());
+        for particle in particles.iter_mut() {
+            particle.net_force = Vector2::new(0.0, 0.0);
+            qt.calculate_force(particle, config);
+        }
+    }
+
+    #[test]
+    fn two_charges_push_each_other_apart_equally() {
+        let config = SimConfig {
+            g: 0.0,
+            ..SimConfig::default()
+        };
+        let mut particles = vec![
+            Particle::new(Vector2::new(100.0, 256.0), Vector2::zeros(), 1.0, 1.0, 0)
+                .with_charge(1.0),
+            Particle::new(Vector2::new(400.0, 256.0), Vector2::zeros(), 1.0, 1.0, 1)
+                .with_charge(1.0),
+        ];
+        net_forces(&mut particles, &config);
+
+        let expected = config.coulomb / (300.0f32.powi(2) + config.softening.powi(2));
+        assert!((particles[0].net_force.x + expected).abs() < expected * 1e-5);
+        assert!((particles[1].net_force.x - expected).abs() < expected * 1e-5);
+        assert_eq!(particles[0].net_force.y, 0.0);
+
+        let qt = create_quadtree(&particles, config.world_size());
+        let first = qt.calculate_potential(&particles[0], &config);
+        let second = qt.calculate_potential(&particles[1], &config);
+        assert!(first > 0.0);
+        assert!((first - second).abs() < first * 1e-6);
+    }
+
+    #[test]
+    fn every_particle_counts_towards_the_nodes() {
+        let config = SimConfig::default();
+        let particles: Vec<Particle> = (0..50)
+            .map(|i| {
+                let angle = i as f32 * 0.7;
+                let pos =
+                    Vector2::new(256.0, 256.0) + Vector2::new(angle.cos(), angle.sin()) * i as f32;
+                Particle::new(pos, Vector2::zeros(), 1.0 + i as f32, 1.0, i)
+                    .with_charge(if i % 2 == 0 { 1.0 } else { -0.5 })
+            })
+            .collect();
+        let qt = create_quadtree(&particles, config.world_size());
+
+        let mass: f32 = particles.iter().map(|p| p.mass).sum();
+        let center = particles
+            .iter()
+            .map(|p| p.pos * p.mass)
+            .sum::<Vector2<f32>>()
+            / mass;
+        assert!((qt.mass - mass).abs() < 1e-3);
+        assert!((qt.m_center_pos - center).norm() < 1e-3);
+        assert!((qt.positive_charge - 25.0).abs() < 1e-5);
+        assert!((qt.negative_charge + 12.5).abs() < 1e-5);
+        assert_eq!(qt.query(&qt.bounds.clone()).len(), particles.len());
+    }
+
+    #[test]
+    fn coincident_particles_feel_no_force_from_each_other() {
+        let config = SimConfig::default();
+        let pos = Vector2::new(200.0, 200.0);
+        let mut particles: Vec<Particle> = (0..4)
+            .map(|i| Particle::new(pos, Vector2::zeros(), 1.0, 1.0, i))
+            .collect();
+        net_forces(&mut particles, &config);
+        for particle in &particles {
+            assert_eq!(particle.net_force, Vector2::zeros());
+        }
+    }
+}
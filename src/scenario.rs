@@ -0,0 +1,1367 @@
+use crate::config::SimConfig;
+use crate::cosmology::{spawn_random_field, FieldSpec, MeanDensity};
+use crate::emitters::Emitter;
+use crate::export::import_particles;
+use crate::forces::ForceField;
+use crate::generators::{
+    create_collision, create_galaxy, spawn_annulus, spawn_circle, spawn_clumps,
+    spawn_exponential_disk, spawn_lattice, spawn_plummer, spawn_rotating_disk, spawn_spiral_galaxy,
+    spawn_uniform_disk, AnnulusSpec, CollisionOrbit, EccentricityDistribution, GalaxySpec, Spin,
+    SpiralArms, GALAXY_PADDING,
+};
+use crate::kepler::{
+    build_system, closest_periapsis, Body, OrbitalElements, Satellite, MIN_PERIAPSIS_SOFTENINGS,
+};
+use crate::particle::Particle;
+use crate::potentials::{ExternalPotential, Harmonic, Hernquist, Logarithmic, Nfw, PointMass};
+use nalgebra::Vector2;
+use rand::Rng;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_SCENARIO: &str = include_str!("../scenarios/default.toml");
+
+// every component type is its own array of tables, e.g. `[[galaxy]]`, and they are
+// spawned in the order: galaxies, collisions, disks, spirals, circles, uniform disks,
+// rotating disks, lattices, random fields, rings, clumps, plummer spheres, systems, particles, files.
+// An optional `[config]` table overrides the default physics parameters and
+// `[[potential]]` and `[[field]]` tables add static external potentials and force fields.
+// `[[emitter]]` tables keep spawning particles while the simulation runs
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default)]
+    pub config: SimConfig,
+    #[serde(default)]
+    pub galaxy: Vec<GalaxyComponent>,
+    #[serde(default)]
+    pub collision: Vec<CollisionComponent>,
+    #[serde(default)]
+    pub disk: Vec<DiskComponent>,
+    #[serde(default)]
+    pub spiral: Vec<SpiralComponent>,
+    #[serde(default)]
+    pub circle: Vec<CircleComponent>,
+    #[serde(default)]
+    pub uniform_disk: Vec<UniformDiskComponent>,
+    #[serde(default)]
+    pub rotating_disk: Vec<RotatingDiskComponent>,
+    #[serde(default)]
+    pub lattice: Vec<LatticeComponent>,
+    #[serde(default)]
+    pub random_field: Vec<RandomFieldComponent>,
+    #[serde(default)]
+    pub ring: Vec<RingComponent>,
+    #[serde(default)]
+    pub clumps: Vec<ClumpsComponent>,
+    #[serde(default)]
+    pub plummer: Vec<PlummerComponent>,
+    #[serde(default)]
+    pub system: Vec<SystemComponent>,
+    #[serde(default)]
+    pub particle: Vec<ParticleComponent>,
+    #[serde(default)]
+    pub file: Vec<FileComponent>,
+    #[serde(default)]
+    pub potential: Vec<PotentialComponent>,
+    #[serde(default)]
+    pub field: Vec<ForceField>,
+    #[serde(default)]
+    pub emitter: Vec<Emitter>,
+    // directory relative `file` paths are resolved against
+    #[serde(skip)]
+    base_dir: PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GalaxyComponent {
+    pub center: [f32; 2],
+    #[serde(default)]
+    pub initial_vel: [f32; 2],
+    pub radius: f32,
+    pub sun_mass: f32,
+    pub particle_mass: f32,
+    pub particles_amount: i32,
+    #[serde(default)]
+    pub spin: Spin,
+    pub gas: Option<GasComponent>,
+    // the sun becomes a sink swallowing every particle closer than this
+    pub accretion_radius: Option<f32>,
+}
+
+// two galaxies on a Keplerian orbit, given as `[collision.first]` and `[collision.second]`
+// tables, with their center of mass resting at `center`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CollisionComponent {
+    pub center: [f32; 2],
+    pub pericenter: f32,
+    pub eccentricity: f32,
+    pub separation: f32,
+    pub first: CollidingGalaxy,
+    pub second: CollidingGalaxy,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CollidingGalaxy {
+    pub radius: f32,
+    pub sun_mass: f32,
+    pub particle_mass: f32,
+    pub particles_amount: i32,
+    #[serde(default)]
+    pub spin: Spin,
+    pub gas: Option<GasComponent>,
+    pub accretion_radius: Option<f32>,
+}
+
+impl CollidingGalaxy {
+    fn spec(&self) -> GalaxySpec {
+        GalaxySpec {
+            center: Vector2::default(),
+            initial_vel: Vector2::default(),
+            radius: self.radius,
+            sun_mass: self.sun_mass,
+            particle_mass: self.particle_mass,
+            particles_amount: self.particles_amount,
+            spin: self.spin,
+            accretion_radius: self.accretion_radius,
+        }
+    }
+}
+
+// exponential disk galaxy, `disk_mass` is the total mass of the disk particles
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiskComponent {
+    pub center: [f32; 2],
+    #[serde(default)]
+    pub initial_vel: [f32; 2],
+    pub scale_length: f32,
+    pub disk_mass: f32,
+    #[serde(default)]
+    pub central_mass: f32,
+    #[serde(default = "default_toomre_q")]
+    pub toomre_q: f32,
+    pub particles_amount: i32,
+    pub gas: Option<GasComponent>,
+}
+
+// exponential disk with its particles along logarithmic spiral arms and an optional bar
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpiralComponent {
+    pub center: [f32; 2],
+    #[serde(default)]
+    pub initial_vel: [f32; 2],
+    pub scale_length: f32,
+    pub disk_mass: f32,
+    #[serde(default)]
+    pub central_mass: f32,
+    #[serde(default = "default_arms")]
+    pub arms: u32,
+    // in degrees
+    #[serde(default = "default_pitch_angle")]
+    pub pitch_angle: f32,
+    pub arm_width: f32,
+    #[serde(default)]
+    pub bar_length: f32,
+    pub particles_amount: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CircleComponent {
+    pub center: [f32; 2],
+    pub radius: f32,
+    pub particle_mass: f32,
+    pub particles_amount: i32,
+    #[serde(default)]
+    pub charge: f32,
+}
+
+// uniform disk at rest, `perturbation` is the standard deviation of random velocities
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UniformDiskComponent {
+    pub center: [f32; 2],
+    pub radius: f32,
+    pub particle_mass: f32,
+    pub particles_amount: i32,
+    #[serde(default)]
+    pub perturbation: f32,
+    #[serde(default)]
+    pub charge: f32,
+    pub gas: Option<GasComponent>,
+}
+
+// cold uniform disk rotating with `spin_parameter` times the circular velocity
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RotatingDiskComponent {
+    pub center: [f32; 2],
+    pub radius: f32,
+    pub particle_mass: f32,
+    pub particles_amount: i32,
+    pub spin_parameter: f32,
+    pub gas: Option<GasComponent>,
+}
+
+// `columns` x `rows` grid, `perturbation` is the largest random offset along each axis
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LatticeComponent {
+    pub center: [f32; 2],
+    pub spacing: f32,
+    pub columns: u32,
+    pub rows: u32,
+    pub particle_mass: f32,
+    #[serde(default)]
+    pub perturbation: f32,
+    #[serde(default)]
+    pub charge: f32,
+}
+
+// lattice displaced by a Gaussian random field, `grid` is a power of two.
+// `displacement_rms` sets the amplitude, `hubble` the velocities (zero is at rest)
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RandomFieldComponent {
+    pub center: [f32; 2],
+    pub box_size: f32,
+    pub grid: u32,
+    pub particle_mass: f32,
+    pub spectral_index: f32,
+    #[serde(default)]
+    pub smoothing: f32,
+    pub displacement_rms: f32,
+    #[serde(default)]
+    pub hubble: f32,
+}
+
+// ring or annulus of test particles on Keplerian orbits around a central body,
+// the radii bound the semi-major axes. Set `central_body = false` for the
+// other rings around the same body
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RingComponent {
+    pub center: [f32; 2],
+    #[serde(default)]
+    pub initial_vel: [f32; 2],
+    pub inner_radius: f32,
+    pub outer_radius: f32,
+    pub central_mass: f32,
+    pub particle_mass: f32,
+    pub particles_amount: i32,
+    #[serde(default)]
+    pub eccentricity: EccentricityDistribution,
+    #[serde(default = "default_central_body")]
+    pub central_body: bool,
+}
+
+// `clumps` knots of `clump_radius` on orbits like the ones of a ring,
+// `particles_amount` is split between them
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClumpsComponent {
+    pub center: [f32; 2],
+    #[serde(default)]
+    pub initial_vel: [f32; 2],
+    pub inner_radius: f32,
+    pub outer_radius: f32,
+    pub central_mass: f32,
+    pub particle_mass: f32,
+    pub particles_amount: i32,
+    #[serde(default)]
+    pub eccentricity: EccentricityDistribution,
+    #[serde(default = "default_central_body")]
+    pub central_body: bool,
+    pub clumps: u32,
+    pub clump_radius: f32,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn validate_annulus(
+    component: &str,
+    i: usize,
+    inner_radius: f32,
+    outer_radius: f32,
+    central_mass: f32,
+    particle_mass: f32,
+    particles_amount: i32,
+    eccentricity: &EccentricityDistribution,
+) -> io::Result<()> {
+    check_positive(component, i, "inner_radius", inner_radius)?;
+    check_positive(component, i, "outer_radius", outer_radius)?;
+    if outer_radius < inner_radius {
+        return Err(invalid_data(format!(
+            "{} #{}: `outer_radius` can't be less than `inner_radius`",
+            component,
+            i + 1
+        )));
+    }
+    check_positive(component, i, "central_mass", central_mass)?;
+    check_positive(component, i, "particle_mass", particle_mass)?;
+    check_positive(component, i, "particles_amount", particles_amount as f32)?;
+    eccentricity.validate().map_err(|message| {
+        invalid_data(format!(
+            "{} #{}: eccentricity: {}",
+            component,
+            i + 1,
+            message
+        ))
+    })
+}
+
+// star cluster in virial equilibrium, `mass` is the total mass of the cluster
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlummerComponent {
+    pub center: [f32; 2],
+    #[serde(default)]
+    pub initial_vel: [f32; 2],
+    pub scale_radius: f32,
+    pub mass: f32,
+    pub particles_amount: i32,
+}
+
+// hierarchical system of bodies on Keplerian orbits, e.g. a star with planets and moons.
+// Satellites are nested `[[system.satellite]]`, `[[system.satellite.satellite]]` tables
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SystemComponent {
+    // finds the body among the particles of `build_named`, the same for satellites
+    pub name: Option<String>,
+    pub center: [f32; 2],
+    #[serde(default)]
+    pub initial_vel: [f32; 2],
+    pub mass: f32,
+    #[serde(default = "default_radius")]
+    pub radius: f32,
+    #[serde(default)]
+    pub satellite: Vec<SatelliteComponent>,
+}
+
+// angles are in degrees
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SatelliteComponent {
+    pub name: Option<String>,
+    pub mass: f32,
+    #[serde(default = "default_radius")]
+    pub radius: f32,
+    pub semi_major_axis: f32,
+    #[serde(default)]
+    pub eccentricity: f32,
+    #[serde(default)]
+    pub argument_of_periapsis: f32,
+    #[serde(default)]
+    pub mean_anomaly: f32,
+    #[serde(default)]
+    pub satellite: Vec<SatelliteComponent>,
+}
+
+fn satellites(components: &[SatelliteComponent]) -> Vec<Satellite> {
+    components
+        .iter()
+        .map(|satellite| Satellite {
+            orbit: OrbitalElements {
+                semi_major_axis: satellite.semi_major_axis,
+                eccentricity: satellite.eccentricity,
+                argument_of_periapsis: satellite.argument_of_periapsis,
+                mean_anomaly: satellite.mean_anomaly,
+            },
+            body: Body {
+                mass: satellite.mass,
+                radius: satellite.radius,
+                satellites: satellites(&satellite.satellite),
+            },
+        })
+        .collect()
+}
+
+// particles of a system come in the order of `build_system`: the body, then every
+// satellite followed by its own satellites. `index` is the index of the next body
+fn name_bodies(
+    name: Option<&String>,
+    components: &[SatelliteComponent],
+    index: &mut usize,
+    names: &mut HashMap<String, usize>,
+) -> io::Result<()> {
+    if let Some(name) = name {
+        if names.insert(name.clone(), *index).is_some() {
+            return Err(invalid_data(format!(
+                "system: `name` \"{}\" is used more than once",
+                name
+            )));
+        }
+    }
+    *index += 1;
+    for satellite in components {
+        name_bodies(satellite.name.as_ref(), &satellite.satellite, index, names)?;
+    }
+    Ok(())
+}
+
+// `path` is the key path of the satellites, e.g. `satellite[0].satellite`
+fn validate_satellites(i: usize, path: &str, components: &[SatelliteComponent]) -> io::Result<()> {
+    for (j, satellite) in components.iter().enumerate() {
+        let path = format!("{}[{}]", path, j);
+        let field = |field| format!("{}.{}", path, field);
+        check_positive("system", i, &field("mass"), satellite.mass)?;
+        check_positive("system", i, &field("radius"), satellite.radius)?;
+        check_positive(
+            "system",
+            i,
+            &field("semi_major_axis"),
+            satellite.semi_major_axis,
+        )?;
+        check_non_negative("system", i, &field("eccentricity"), satellite.eccentricity)?;
+        if satellite.eccentricity >= 1.0 {
+            return Err(invalid_data(format!(
+                "system #{}: `{}` has to be less than 1 for a bound orbit, got {}",
+                i + 1,
+                field("eccentricity"),
+                satellite.eccentricity
+            )));
+        }
+        validate_satellites(i, &field("satellite"), &satellite.satellite)?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParticleComponent {
+    pub pos: [f32; 2],
+    #[serde(default)]
+    pub vel: [f32; 2],
+    pub mass: f32,
+    #[serde(default = "default_radius")]
+    pub radius: f32,
+    #[serde(default)]
+    pub charge: f32,
+    // makes the particle a sink, e.g. a seed black hole or a protostar
+    pub accretion_radius: Option<f32>,
+}
+
+// e.g. `gas = { fraction = 0.2, internal_energy = 0.01 }` in a galaxy or disk table turns a
+// random `fraction` of its particles into SPH gas. Central bodies, which are heavier than
+// the other particles of the component, stay collisionless
+#[derive(Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct GasComponent {
+    pub fraction: f32,
+    pub internal_energy: f32,
+    // initial guess, smoothing lengths adapt to the neighbours within a few steps
+    #[serde(default = "default_smoothing_length")]
+    pub smoothing_length: f32,
+}
+
+impl GasComponent {
+    fn validate(&self, component: &str, i: usize) -> io::Result<()> {
+        check_non_negative(component, i, "gas.fraction", self.fraction)?;
+        if self.fraction > 1.0 {
+            return Err(invalid_data(format!(
+                "{} #{}: `gas.fraction` can't be more than 1, got {}",
+                component,
+                i + 1,
+                self.fraction
+            )));
+        }
+        check_non_negative(component, i, "gas.internal_energy", self.internal_energy)?;
+        check_positive(component, i, "gas.smoothing_length", self.smoothing_length)
+    }
+}
+
+fn make_gas(
+    particles: &mut [Particle],
+    rng: &mut impl Rng,
+    gas: Option<&GasComponent>,
+    particle_mass: f32,
+) {
+    let Some(gas) = gas else {
+        return;
+    };
+    for particle in particles {
+        if particle.mass <= particle_mass && rng.gen::<f32>() < gas.fraction {
+            *particle = particle.with_gas(gas.internal_energy, gas.smoothing_length);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileComponent {
+    pub path: PathBuf,
+}
+
+// e.g. `kind = "nfw"`, every kind with its own parameters
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PotentialComponent {
+    PointMass {
+        center: [f32; 2],
+        mass: f32,
+    },
+    Hernquist {
+        center: [f32; 2],
+        mass: f32,
+        scale_radius: f32,
+    },
+    Nfw {
+        center: [f32; 2],
+        mass: f32,
+        scale_radius: f32,
+    },
+    Logarithmic {
+        center: [f32; 2],
+        circular_vel: f32,
+        #[serde(default)]
+        core_radius: f32,
+    },
+    Harmonic {
+        center: [f32; 2],
+        frequency: f32,
+    },
+}
+
+fn default_radius() -> f32 {
+    1.0
+}
+
+fn default_smoothing_length() -> f32 {
+    2.0
+}
+
+fn default_toomre_q() -> f32 {
+    1.5
+}
+
+fn default_central_body() -> bool {
+    true
+}
+
+fn default_arms() -> u32 {
+    2
+}
+
+fn default_pitch_angle() -> f32 {
+    15.0
+}
+
+// every particle spawned by a component gets its `charge`
+fn set_charge(particles: &mut [Particle], charge: f32) {
+    for particle in particles {
+        particle.charge = charge;
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn check_positive(component: &str, i: usize, field: &str, value: f32) -> io::Result<()> {
+    if value > 0.0 && value.is_finite() {
+        Ok(())
+    } else {
+        Err(invalid_data(format!(
+            "{} #{}: `{}` has to be positive, got {}",
+            component,
+            i + 1,
+            field,
+            value
+        )))
+    }
+}
+
+// disk particles are spawned between `GALAXY_PADDING` and the radius
+fn check_galaxy_radius(component: &str, i: usize, field: &str, radius: f32) -> io::Result<()> {
+    if radius > GALAXY_PADDING && radius.is_finite() {
+        Ok(())
+    } else {
+        Err(invalid_data(format!(
+            "{} #{}: `{}` has to be more than {}, got {}",
+            component,
+            i + 1,
+            field,
+            GALAXY_PADDING,
+            radius
+        )))
+    }
+}
+
+fn check_finite(component: &str, i: usize, field: &str, value: f32) -> io::Result<()> {
+    if value.is_finite() {
+        Ok(())
+    } else {
+        Err(invalid_data(format!(
+            "{} #{}: `{}` has to be finite, got {}",
+            component,
+            i + 1,
+            field,
+            value
+        )))
+    }
+}
+
+fn check_non_negative(component: &str, i: usize, field: &str, value: f32) -> io::Result<()> {
+    if value >= 0.0 && value.is_finite() {
+        Ok(())
+    } else {
+        Err(invalid_data(format!(
+            "{} #{}: `{}` can't be negative, got {}",
+            component,
+            i + 1,
+            field,
+            value
+        )))
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let mut scenario = Self::parse(&content)
+            .map_err(|parsing_err| invalid_data(format!("{}: {}", path.display(), parsing_err)))?;
+        scenario.base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(scenario)
+    }
+
+    // toml errors name the offending key together with its line and column
+    pub fn parse(content: &str) -> io::Result<Self> {
+        let scenario: Scenario =
+            toml::from_str(content).map_err(|parsing_err| invalid_data(parsing_err.to_string()))?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        self.config
+            .validate()
+            .map_err(|message| invalid_data(format!("config: {}", message)))?;
+        for (i, galaxy) in self.galaxy.iter().enumerate() {
+            check_galaxy_radius("galaxy", i, "radius", galaxy.radius)?;
+            check_positive("galaxy", i, "sun_mass", galaxy.sun_mass)?;
+            check_positive("galaxy", i, "particle_mass", galaxy.particle_mass)?;
+            check_positive(
+                "galaxy",
+                i,
+                "particles_amount",
+                galaxy.particles_amount as f32,
+            )?;
+            if let Some(gas) = &galaxy.gas {
+                gas.validate("galaxy", i)?;
+            }
+            if let Some(accretion_radius) = galaxy.accretion_radius {
+                check_positive("galaxy", i, "accretion_radius", accretion_radius)?;
+            }
+        }
+        for (i, collision) in self.collision.iter().enumerate() {
+            check_positive("collision", i, "pericenter", collision.pericenter)?;
+            check_non_negative("collision", i, "eccentricity", collision.eccentricity)?;
+            check_positive("collision", i, "separation", collision.separation)?;
+            if collision.separation < collision.pericenter {
+                return Err(invalid_data(format!(
+                    "collision #{}: `separation` can't be less than `pericenter`",
+                    i + 1
+                )));
+            }
+            if collision.eccentricity < 1.0 {
+                let apocenter = collision.pericenter * (1.0 + collision.eccentricity)
+                    / (1.0 - collision.eccentricity);
+                if collision.separation > apocenter * (1.0 + 1e-4) {
+                    return Err(invalid_data(format!(
+                        "collision #{}: `separation` can't be more than the apocenter of the orbit, {}",
+                        i + 1,
+                        apocenter
+                    )));
+                }
+            }
+            for (name, galaxy) in [("first", &collision.first), ("second", &collision.second)] {
+                let field = |field| format!("{}.{}", name, field);
+                check_galaxy_radius("collision", i, &field("radius"), galaxy.radius)?;
+                check_positive("collision", i, &field("sun_mass"), galaxy.sun_mass)?;
+                check_positive(
+                    "collision",
+                    i,
+                    &field("particle_mass"),
+                    galaxy.particle_mass,
+                )?;
+                check_positive(
+                    "collision",
+                    i,
+                    &field("particles_amount"),
+                    galaxy.particles_amount as f32,
+                )?;
+                if let Some(gas) = &galaxy.gas {
+                    gas.validate("collision", i)?;
+                }
+                if let Some(accretion_radius) = galaxy.accretion_radius {
+                    check_positive("collision", i, &field("accretion_radius"), accretion_radius)?;
+                }
+            }
+        }
+        for (i, disk) in self.disk.iter().enumerate() {
+            check_positive("disk", i, "scale_length", disk.scale_length)?;
+            check_positive("disk", i, "disk_mass", disk.disk_mass)?;
+            check_non_negative("disk", i, "central_mass", disk.central_mass)?;
+            check_positive("disk", i, "toomre_q", disk.toomre_q)?;
+            check_positive("disk", i, "particles_amount", disk.particles_amount as f32)?;
+            if let Some(gas) = &disk.gas {
+                gas.validate("disk", i)?;
+            }
+        }
+        for (i, spiral) in self.spiral.iter().enumerate() {
+            check_positive("spiral", i, "scale_length", spiral.scale_length)?;
+            check_positive("spiral", i, "disk_mass", spiral.disk_mass)?;
+            check_non_negative("spiral", i, "central_mass", spiral.central_mass)?;
+            check_positive("spiral", i, "arms", spiral.arms as f32)?;
+            check_positive("spiral", i, "pitch_angle", spiral.pitch_angle)?;
+            if spiral.pitch_angle >= 90.0 {
+                return Err(invalid_data(format!(
+                    "spiral #{}: `pitch_angle` has to be less than 90 degrees, got {}",
+                    i + 1,
+                    spiral.pitch_angle
+                )));
+            }
+            check_non_negative("spiral", i, "arm_width", spiral.arm_width)?;
+            check_non_negative("spiral", i, "bar_length", spiral.bar_length)?;
+            check_positive(
+                "spiral",
+                i,
+                "particles_amount",
+                spiral.particles_amount as f32,
+            )?;
+        }
+        for (i, circle) in self.circle.iter().enumerate() {
+            check_positive("circle", i, "radius", circle.radius)?;
+            check_positive("circle", i, "particle_mass", circle.particle_mass)?;
+            check_positive(
+                "circle",
+                i,
+                "particles_amount",
+                circle.particles_amount as f32,
+            )?;
+            check_finite("circle", i, "charge", circle.charge)?;
+        }
+        for (i, disk) in self.uniform_disk.iter().enumerate() {
+            check_positive("uniform_disk", i, "radius", disk.radius)?;
+            check_positive("uniform_disk", i, "particle_mass", disk.particle_mass)?;
+            check_positive(
+                "uniform_disk",
+                i,
+                "particles_amount",
+                disk.particles_amount as f32,
+            )?;
+            check_non_negative("uniform_disk", i, "perturbation", disk.perturbation)?;
+            check_finite("uniform_disk", i, "charge", disk.charge)?;
+            if let Some(gas) = &disk.gas {
+                gas.validate("uniform_disk", i)?;
+            }
+        }
+        for (i, disk) in self.rotating_disk.iter().enumerate() {
+            check_positive("rotating_disk", i, "radius", disk.radius)?;
+            check_positive("rotating_disk", i, "particle_mass", disk.particle_mass)?;
+            check_positive(
+                "rotating_disk",
+                i,
+                "particles_amount",
+                disk.particles_amount as f32,
+            )?;
+            check_non_negative("rotating_disk", i, "spin_parameter", disk.spin_parameter)?;
+            if let Some(gas) = &disk.gas {
+                gas.validate("rotating_disk", i)?;
+            }
+        }
+        for (i, lattice) in self.lattice.iter().enumerate() {
+            check_positive("lattice", i, "spacing", lattice.spacing)?;
+            check_positive("lattice", i, "columns", lattice.columns as f32)?;
+            check_positive("lattice", i, "rows", lattice.rows as f32)?;
+            check_positive("lattice", i, "particle_mass", lattice.particle_mass)?;
+            check_non_negative("lattice", i, "perturbation", lattice.perturbation)?;
+            check_finite("lattice", i, "charge", lattice.charge)?;
+        }
+        for (i, field) in self.random_field.iter().enumerate() {
+            check_positive("random_field", i, "box_size", field.box_size)?;
+            if field.grid < 2 || !field.grid.is_power_of_two() {
+                return Err(invalid_data(format!(
+                    "random_field #{}: `grid` has to be a power of two, got {}",
+                    i + 1,
+                    field.grid
+                )));
+            }
+            check_positive("random_field", i, "particle_mass", field.particle_mass)?;
+            if !field.spectral_index.is_finite() {
+                return Err(invalid_data(format!(
+                    "random_field #{}: `spectral_index` has to be finite",
+                    i + 1
+                )));
+            }
+            check_non_negative("random_field", i, "smoothing", field.smoothing)?;
+            check_non_negative(
+                "random_field",
+                i,
+                "displacement_rms",
+                field.displacement_rms,
+            )?;
+            check_non_negative("random_field", i, "hubble", field.hubble)?;
+        }
+        for (i, ring) in self.ring.iter().enumerate() {
+            validate_annulus(
+                "ring",
+                i,
+                ring.inner_radius,
+                ring.outer_radius,
+                ring.central_mass,
+                ring.particle_mass,
+                ring.particles_amount,
+                &ring.eccentricity,
+            )?;
+        }
+        for (i, clumps) in self.clumps.iter().enumerate() {
+            validate_annulus(
+                "clumps",
+                i,
+                clumps.inner_radius,
+                clumps.outer_radius,
+                clumps.central_mass,
+                clumps.particle_mass,
+                clumps.particles_amount,
+                &clumps.eccentricity,
+            )?;
+            check_positive("clumps", i, "clumps", clumps.clumps as f32)?;
+            check_positive("clumps", i, "clump_radius", clumps.clump_radius)?;
+        }
+        for (i, plummer) in self.plummer.iter().enumerate() {
+            check_positive("plummer", i, "scale_radius", plummer.scale_radius)?;
+            check_positive("plummer", i, "mass", plummer.mass)?;
+            check_positive(
+                "plummer",
+                i,
+                "particles_amount",
+                plummer.particles_amount as f32,
+            )?;
+        }
+        for (i, system) in self.system.iter().enumerate() {
+            check_positive("system", i, "mass", system.mass)?;
+            check_positive("system", i, "radius", system.radius)?;
+            validate_satellites(i, "satellite", &system.satellite)?;
+        }
+        for (i, potential) in self.potential.iter().enumerate() {
+            match *potential {
+                PotentialComponent::PointMass { mass, .. } => {
+                    check_positive("potential", i, "mass", mass)?;
+                }
+                PotentialComponent::Hernquist {
+                    mass, scale_radius, ..
+                }
+                | PotentialComponent::Nfw {
+                    mass, scale_radius, ..
+                } => {
+                    check_positive("potential", i, "mass", mass)?;
+                    check_positive("potential", i, "scale_radius", scale_radius)?;
+                }
+                PotentialComponent::Logarithmic {
+                    circular_vel,
+                    core_radius,
+                    ..
+                } => {
+                    check_positive("potential", i, "circular_vel", circular_vel)?;
+                    check_non_negative("potential", i, "core_radius", core_radius)?;
+                }
+                PotentialComponent::Harmonic { frequency, .. } => {
+                    check_positive("potential", i, "frequency", frequency)?;
+                }
+            }
+        }
+        for (i, field) in self.field.iter().enumerate() {
+            field
+                .validate()
+                .map_err(|message| invalid_data(format!("field #{}: {}", i + 1, message)))?;
+        }
+        for (i, particle) in self.particle.iter().enumerate() {
+            check_positive("particle", i, "mass", particle.mass)?;
+            check_positive("particle", i, "radius", particle.radius)?;
+            check_finite("particle", i, "charge", particle.charge)?;
+            if let Some(accretion_radius) = particle.accretion_radius {
+                check_positive("particle", i, "accretion_radius", accretion_radius)?;
+            }
+        }
+        for (i, emitter) in self.emitter.iter().enumerate() {
+            emitter
+                .validate()
+                .map_err(|message| invalid_data(format!("emitter #{}: {}", i + 1, message)))?;
+        }
+        Ok(())
+    }
+
+    pub fn override_particles_amount(&mut self, particles_amount: i32) {
+        for galaxy in self.galaxy.iter_mut() {
+            galaxy.particles_amount = particles_amount;
+        }
+        for collision in self.collision.iter_mut() {
+            collision.first.particles_amount = particles_amount;
+            collision.second.particles_amount = particles_amount;
+        }
+        for disk in self.disk.iter_mut() {
+            disk.particles_amount = particles_amount;
+        }
+        for spiral in self.spiral.iter_mut() {
+            spiral.particles_amount = particles_amount;
+        }
+        for circle in self.circle.iter_mut() {
+            circle.particles_amount = particles_amount;
+        }
+        for disk in self.uniform_disk.iter_mut() {
+            disk.particles_amount = particles_amount;
+        }
+        for disk in self.rotating_disk.iter_mut() {
+            disk.particles_amount = particles_amount;
+        }
+        // the closest square grid
+        let side = (particles_amount as f32).sqrt().round().max(1.0) as u32;
+        for lattice in self.lattice.iter_mut() {
+            lattice.columns = side;
+            lattice.rows = side;
+        }
+        // the closest power of two grid
+        let grid = 2u32.pow(((side as f32).log2().round() as u32).max(1));
+        for field in self.random_field.iter_mut() {
+            field.grid = grid;
+        }
+        for ring in self.ring.iter_mut() {
+            ring.particles_amount = particles_amount;
+        }
+        for clumps in self.clumps.iter_mut() {
+            clumps.particles_amount = particles_amount;
+        }
+        for plummer in self.plummer.iter_mut() {
+            plummer.particles_amount = particles_amount;
+        }
+    }
+
+    // in the comoving mode the mean density of every random field is taken away
+    pub fn potentials(&self, config: &SimConfig) -> Vec<Box<dyn ExternalPotential>> {
+        let mut potentials: Vec<Box<dyn ExternalPotential>> = self
+            .potential
+            .iter()
+            .map(|potential| -> Box<dyn ExternalPotential> {
+                match *potential {
+                    PotentialComponent::PointMass { center, mass } => Box::new(PointMass {
+                        center: Vector2::from(center),
+                        mass,
+                    }),
+                    PotentialComponent::Hernquist {
+                        center,
+                        mass,
+                        scale_radius,
+                    } => Box::new(Hernquist {
+                        center: Vector2::from(center),
+                        mass,
+                        scale_radius,
+                    }),
+                    PotentialComponent::Nfw {
+                        center,
+                        mass,
+                        scale_radius,
+                    } => Box::new(Nfw {
+                        center: Vector2::from(center),
+                        mass,
+                        scale_radius,
+                    }),
+                    PotentialComponent::Logarithmic {
+                        center,
+                        circular_vel,
+                        core_radius,
+                    } => Box::new(Logarithmic {
+                        center: Vector2::from(center),
+                        circular_vel,
+                        core_radius,
+                    }),
+                    PotentialComponent::Harmonic { center, frequency } => Box::new(Harmonic {
+                        center: Vector2::from(center),
+                        frequency,
+                    }),
+                }
+            })
+            .collect();
+        if config.cosmology.is_some() {
+            for field in &self.random_field {
+                potentials.push(Box::new(MeanDensity::new(
+                    Vector2::from(field.center),
+                    field.box_size,
+                    field.grid,
+                    field.particle_mass,
+                    config.world_size(),
+                )));
+            }
+        }
+        potentials
+    }
+
+    // `config` may differ from the scenario one when it's overridden from the command line
+    pub fn build(&self, config: &SimConfig, rng: &mut impl Rng) -> io::Result<Vec<Particle>> {
+        self.build_named(config, rng)
+            .map(|(particles, _)| particles)
+    }
+
+    // also returns the `Particle::index` of every named system body and satellite
+    pub fn build_named(
+        &self,
+        config: &SimConfig,
+        rng: &mut impl Rng,
+    ) -> io::Result<(Vec<Particle>, HashMap<String, usize>)> {
+        let mut particles = Vec::new();
+        let mut names = HashMap::new();
+        for galaxy in &self.galaxy {
+            let spec = GalaxySpec {
+                center: Vector2::from(galaxy.center),
+                initial_vel: Vector2::from(galaxy.initial_vel),
+                radius: galaxy.radius,
+                sun_mass: galaxy.sun_mass,
+                particle_mass: galaxy.particle_mass,
+                particles_amount: galaxy.particles_amount,
+                spin: galaxy.spin,
+                accretion_radius: galaxy.accretion_radius,
+            };
+            let start = particles.len();
+            create_galaxy(&mut particles, rng, config, &spec);
+            make_gas(
+                &mut particles[start..],
+                rng,
+                galaxy.gas.as_ref(),
+                galaxy.particle_mass,
+            );
+        }
+        for collision in &self.collision {
+            let start = particles.len();
+            create_collision(
+                &mut particles,
+                rng,
+                config,
+                Vector2::from(collision.center),
+                [&collision.first.spec(), &collision.second.spec()],
+                &CollisionOrbit {
+                    pericenter: collision.pericenter,
+                    eccentricity: collision.eccentricity,
+                    separation: collision.separation,
+                },
+            );
+            // the first galaxy comes first, its disk and then its sun
+            let second_start = start + collision.first.particles_amount as usize + 1;
+            let (first, second) = particles[start..].split_at_mut(second_start - start);
+            make_gas(
+                first,
+                rng,
+                collision.first.gas.as_ref(),
+                collision.first.particle_mass,
+            );
+            make_gas(
+                second,
+                rng,
+                collision.second.gas.as_ref(),
+                collision.second.particle_mass,
+            );
+        }
+        for disk in &self.disk {
+            let start = particles.len();
+            spawn_exponential_disk(
+                &mut particles,
+                rng,
+                config,
+                Vector2::from(disk.center),
+                Vector2::from(disk.initial_vel),
+                disk.scale_length,
+                disk.disk_mass,
+                disk.central_mass,
+                disk.toomre_q,
+                disk.particles_amount,
+            );
+            make_gas(
+                &mut particles[start..],
+                rng,
+                disk.gas.as_ref(),
+                disk.disk_mass / disk.particles_amount as f32,
+            );
+        }
+        for spiral in &self.spiral {
+            spawn_spiral_galaxy(
+                &mut particles,
+                rng,
+                config,
+                Vector2::from(spiral.center),
+                Vector2::from(spiral.initial_vel),
+                spiral.scale_length,
+                spiral.disk_mass,
+                spiral.central_mass,
+                &SpiralArms {
+                    arms: spiral.arms,
+                    pitch_angle: spiral.pitch_angle,
+                    arm_width: spiral.arm_width,
+                    bar_length: spiral.bar_length,
+                },
+                spiral.particles_amount,
+            );
+        }
+        for circle in &self.circle {
+            let start = particles.len();
+            spawn_circle(
+                &mut particles,
+                rng,
+                Vector2::from(circle.center),
+                circle.radius,
+                circle.particle_mass,
+                circle.particles_amount,
+            );
+            set_charge(&mut particles[start..], circle.charge);
+        }
+        for disk in &self.uniform_disk {
+            let start = particles.len();
+            spawn_uniform_disk(
+                &mut particles,
+                rng,
+                Vector2::from(disk.center),
+                disk.radius,
+                disk.particle_mass,
+                disk.particles_amount,
+                disk.perturbation,
+            );
+            set_charge(&mut particles[start..], disk.charge);
+            make_gas(
+                &mut particles[start..],
+                rng,
+                disk.gas.as_ref(),
+                disk.particle_mass,
+            );
+        }
+        for disk in &self.rotating_disk {
+            let start = particles.len();
+            spawn_rotating_disk(
+                &mut particles,
+                rng,
+                config,
+                Vector2::from(disk.center),
+                disk.radius,
+                disk.particle_mass,
+                disk.particles_amount,
+                disk.spin_parameter,
+            );
+            make_gas(
+                &mut particles[start..],
+                rng,
+                disk.gas.as_ref(),
+                disk.particle_mass,
+            );
+        }
+        for lattice in &self.lattice {
+            let start = particles.len();
+            spawn_lattice(
+                &mut particles,
+                rng,
+                Vector2::from(lattice.center),
+                lattice.spacing,
+                (lattice.columns, lattice.rows),
+                lattice.particle_mass,
+                lattice.perturbation,
+            );
+            set_charge(&mut particles[start..], lattice.charge);
+        }
+        for field in &self.random_field {
+            spawn_random_field(
+                &mut particles,
+                rng,
+                Vector2::from(field.center),
+                field.box_size,
+                field.grid,
+                field.particle_mass,
+                &FieldSpec {
+                    spectral_index: field.spectral_index,
+                    smoothing: field.smoothing,
+                    displacement_rms: field.displacement_rms,
+                    hubble: field.hubble,
+                },
+            );
+        }
+        for ring in &self.ring {
+            let annulus = AnnulusSpec {
+                center: Vector2::from(ring.center),
+                initial_vel: Vector2::from(ring.initial_vel),
+                inner_radius: ring.inner_radius,
+                outer_radius: ring.outer_radius,
+                central_mass: ring.central_mass,
+                particle_mass: ring.particle_mass,
+                particles_amount: ring.particles_amount,
+                eccentricity: ring.eccentricity,
+                central_body: ring.central_body,
+            };
+            spawn_annulus(&mut particles, rng, config, &annulus);
+        }
+        for clumps in &self.clumps {
+            let annulus = AnnulusSpec {
+                center: Vector2::from(clumps.center),
+                initial_vel: Vector2::from(clumps.initial_vel),
+                inner_radius: clumps.inner_radius,
+                outer_radius: clumps.outer_radius,
+                central_mass: clumps.central_mass,
+                particle_mass: clumps.particle_mass,
+                particles_amount: clumps.particles_amount,
+                eccentricity: clumps.eccentricity,
+                central_body: clumps.central_body,
+            };
+            spawn_clumps(
+                &mut particles,
+                rng,
+                config,
+                &annulus,
+                clumps.clumps,
+                clumps.clump_radius,
+            );
+        }
+        for plummer in &self.plummer {
+            spawn_plummer(
+                &mut particles,
+                rng,
+                config,
+                Vector2::from(plummer.center),
+                Vector2::from(plummer.initial_vel),
+                plummer.scale_radius,
+                plummer.mass,
+                plummer.particles_amount,
+            );
+        }
+        for (i, system) in self.system.iter().enumerate() {
+            let mut index = particles.len();
+            name_bodies(
+                system.name.as_ref(),
+                &system.satellite,
+                &mut index,
+                &mut names,
+            )?;
+            let body = Body {
+                mass: system.mass,
+                radius: system.radius,
+                satellites: satellites(&system.satellite),
+            };
+            if let Some(periapsis) = closest_periapsis(&body) {
+                if config.softening * MIN_PERIAPSIS_SOFTENINGS > periapsis {
+                    eprintln!(
+                        "system #{}: `softening` {} isn't small compared with the closest periapsis {}, the orbits won't follow their elements",
+                        i + 1,
+                        config.softening,
+                        periapsis
+                    );
+                }
+            }
+            particles.extend(build_system(
+                &body,
+                config,
+                Vector2::from(system.center),
+                Vector2::from(system.initial_vel),
+            ));
+        }
+        for particle in &self.particle {
+            let mut new_particle = Particle::new(
+                Vector2::from(particle.pos),
+                Vector2::from(particle.vel),
+                particle.mass,
+                particle.radius,
+                0,
+            )
+            .with_charge(particle.charge);
+            if let Some(accretion_radius) = particle.accretion_radius {
+                new_particle = new_particle.with_sink(accretion_radius);
+            }
+            particles.push(new_particle);
+        }
+        for file in &self.file {
+            let path = self.base_dir.join(&file.path);
+            let loaded = import_particles(&path).map_err(|loading_err| {
+                invalid_data(format!("file {}: {}", path.display(), loading_err))
+            })?;
+            particles.extend(loaded);
+        }
+
+        // every generator counts indices from zero, but they have to be unique
+        // across the whole scenario so particles don't skip each other in the tree
+        for (i, particle) in particles.iter_mut().enumerate() {
+            particle.index = i;
+        }
+        for (i, emitter) in self.emitter.iter().enumerate() {
+            // particles emitted in the same step would start at the same point
+            if emitter.spread == 0.0 && emitter.rate * config.dt > 1.0 {
+                return Err(invalid_data(format!(
+                    "emitter #{}: `spread` has to be positive to emit more than one particle per step, got `rate` {} with `dt` {}",
+                    i + 1,
+                    emitter.rate,
+                    config.dt
+                )));
+            }
+            if let Some(attach) = emitter.attach {
+                if attach >= particles.len() {
+                    return Err(invalid_data(format!(
+                        "emitter #{}: `attach` has to be the index of a scenario particle, got {} with {} particles",
+                        i + 1,
+                        attach,
+                        particles.len()
+                    )));
+                }
+            }
+        }
+        Ok((particles, names))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_message(content: &str) -> String {
+        Scenario::parse(content)
+            .err()
+            .expect("the scenario should be rejected")
+            .to_string()
+    }
+
+    #[test]
+    fn galaxy_radius_has_to_leave_room_for_the_disk() {
+        let message = error_message(
+            "[[galaxy]]
+            center = [0.0, 0.0]
+            radius = 1.0
+            sun_mass = 100.0
+            particle_mass = 0.01
+            particles_amount = 10",
+        );
+        assert!(message.contains("galaxy #1: `radius`"), "{}", message);
+
+        let message = error_message(
+            "[[collision]]
+            center = [0.0, 0.0]
+            pericenter = 10.0
+            eccentricity = 1.0
+            separation = 50.0
+
+            [collision.first]
+            radius = 20.0
+            sun_mass = 100.0
+            particle_mass = 0.01
+            particles_amount = 10
+
+            [collision.second]
+            radius = 1.0
+            sun_mass = 100.0
+            particle_mass = 0.01
+            particles_amount = 10",
+        );
+        assert!(
+            message.contains("collision #1: `second.radius`"),
+            "{}",
+            message
+        );
+    }
+}
@@ -0,0 +1,352 @@
+use crate::config::{Integrator, SimConfig};
+use crate::consts::{DIAGNOSTICS_INTERVAL, LOG_INTERVAL};
+use crate::cosmology::Cosmology;
+use crate::diagnostics::{Diagnostics, DiagnosticsLog, StepStats};
+use crate::emitters::Emitter;
+use crate::forces::ForceField;
+use crate::generators::{seeded_rng, SimRng};
+use crate::particle::Particle;
+use crate::potentials::ExternalPotential;
+use crate::quadtree::QuadTree;
+use crate::sinks::{self, Accretion};
+use crate::snapshot::Snapshot;
+use crate::sph;
+use crate::utils::{calculate_force, calculate_new_position, create_quadtree, drift, kick};
+use rayon::prelude::*;
+use std::time::Instant;
+
+pub struct Simulation {
+    pub particles: Vec<Particle>,
+    pub qt: QuadTree,
+    pub config: SimConfig,
+    // not part of snapshots, restoring one keeps the current potentials, fields and emitters
+    pub potentials: Vec<Box<dyn ExternalPotential>>,
+    pub fields: Vec<ForceField>,
+    pub emitters: Vec<Emitter>,
+    // emitted particles get indices from here on, so they stay unique
+    pub next_index: usize,
+    pub step: u32,
+    pub sim_time: f32,
+    // stays at 1 without a cosmology, positions are comoving otherwise
+    pub scale_factor: f32,
+    pub log: Option<DiagnosticsLog>,
+    pub initial_diagnostics: Diagnostics,
+    pub diagnostics: Diagnostics,
+    // everything the sinks swallowed, in order
+    pub accretions: Vec<Accretion>,
+    // leapfrog starts every step with a half kick from the forces of the previous one
+    forces_ready: bool,
+    rng: SimRng,
+}
+
+impl Simulation {
+    pub fn new(
+        mut particles: Vec<Particle>,
+        config: SimConfig,
+        potentials: Vec<Box<dyn ExternalPotential>>,
+        fields: Vec<ForceField>,
+        emitters: Vec<Emitter>,
+    ) -> Self {
+        particles.par_sort_by_key(|item| item.mass as u32);
+        let scale_factor = config
+            .cosmology
+            .map_or(1.0, |cosmology| cosmology.initial_scale_factor());
+        let qt = create_quadtree(&particles, config.world_size());
+        let initial_diagnostics = Diagnostics::compute(
+            &particles,
+            &qt,
+            &config.at_scale_factor(scale_factor),
+            &potentials,
+            &fields,
+        );
+        initial_diagnostics.print(0, &initial_diagnostics);
+        print_expansion(&config, scale_factor);
+        // a stream of its own, so emitters don't repeat the random numbers
+        // the scenario was built with
+        let mut rng = seeded_rng(config.seed.unwrap_or_default());
+        rng.set_stream(1);
+
+        Self {
+            next_index: next_index(&particles),
+            particles,
+            qt,
+            config,
+            potentials,
+            fields,
+            emitters,
+            step: 0,
+            sim_time: 0.0,
+            scale_factor,
+            log: None,
+            initial_diagnostics,
+            diagnostics: initial_diagnostics,
+            accretions: Vec::new(),
+            forces_ready: false,
+            rng,
+        }
+    }
+
+    pub fn update(&mut self) {
+        // forces are computed with the scale factor at the start of the step and,
+        // for the second leapfrog kick, at its end
+        let config = self.config.at_scale_factor(self.scale_factor);
+        let end_scale_factor = self.config.cosmology.map_or(1.0, |cosmology| {
+            cosmology.advance(self.scale_factor, config.dt)
+        });
+        let end_config = self.config.at_scale_factor(end_scale_factor);
+        let tree_start = Instant::now();
+        self.qt = create_quadtree(&self.particles, config.world_size());
+        let mut tree_ms = tree_start.elapsed().as_secs_f32() * 1000.0;
+
+        let print_step = self.step > 0 && self.step.is_multiple_of(DIAGNOSTICS_INTERVAL);
+        let log_step = self.log.is_some() && self.step.is_multiple_of(LOG_INTERVAL);
+        let diagnostics_start = Instant::now();
+        if print_step || log_step {
+            self.diagnostics = Diagnostics::compute(
+                &self.particles,
+                &self.qt,
+                &config,
+                &self.potentials,
+                &self.fields,
+            );
+        }
+        let diagnostics_ms = diagnostics_start.elapsed().as_secs_f32() * 1000.0;
+        // every column of the log row describes the same state as the diagnostics,
+        // the one before this step, only the timings are filled in after it
+        let log_stats = log_step.then(|| StepStats {
+            step: self.step,
+            time: self.sim_time,
+            dt: config.dt,
+            particle_count: self.particles.len(),
+            max_speed: self
+                .particles
+                .par_iter()
+                .map(|particle| particle.vel.norm())
+                .reduce(|| 0.0, f32::max),
+            tree_depth: self.qt.depth(),
+            tree_ms: 0.0,
+            force_ms: 0.0,
+            diagnostics_ms,
+            scale_factor: self.scale_factor,
+        });
+        if print_step {
+            self.diagnostics.print(self.step, &self.initial_diagnostics);
+            print_expansion(&config, self.scale_factor);
+            self.print_sinks();
+        }
+
+        let force_start = Instant::now();
+        // comoving velocities decay as a⁻², half of the Hubble drag is applied
+        // before the forces and half after them
+        if let Some(cosmology) = config.cosmology {
+            hubble_drag(
+                &mut self.particles,
+                cosmology.hubble_rate(self.scale_factor),
+                config.dt,
+            );
+        }
+        match config.integrator {
+            Integrator::Euler => {
+                sph::update(&mut self.particles, &self.qt, &config);
+                // self.particles.par_iter_mut().for_each(|particle| {
+                //     calculate_new_position(particle, &self.qt, &config, &self.potentials, &self.fields);
+                // });
+                for i in 0..self.particles.len() {
+                    calculate_new_position(
+                        &mut self.particles[i],
+                        &self.qt,
+                        &config,
+                        &self.potentials,
+                        &self.fields,
+                    );
+                }
+            }
+            Integrator::Leapfrog => {
+                if !self.forces_ready {
+                    sph::update(&mut self.particles, &self.qt, &config);
+                    for particle in self.particles.iter_mut() {
+                        calculate_force(
+                            particle,
+                            &self.qt,
+                            &config,
+                            &self.potentials,
+                            &self.fields,
+                        );
+                    }
+                }
+                for particle in self.particles.iter_mut() {
+                    kick(particle, config.dt / 2.0);
+                    drift(particle, config.dt);
+                }
+
+                let tree_start = Instant::now();
+                self.qt = create_quadtree(&self.particles, config.world_size());
+                tree_ms += tree_start.elapsed().as_secs_f32() * 1000.0;
+
+                sph::update(&mut self.particles, &self.qt, &end_config);
+
+                for particle in self.particles.iter_mut() {
+                    calculate_force(
+                        particle,
+                        &self.qt,
+                        &end_config,
+                        &self.potentials,
+                        &self.fields,
+                    );
+                    kick(particle, config.dt / 2.0);
+                }
+                self.forces_ready = true;
+            }
+        }
+        if let Some(cosmology) = config.cosmology {
+            hubble_drag(
+                &mut self.particles,
+                cosmology.hubble_rate(end_scale_factor),
+                config.dt,
+            );
+        }
+        let force_ms = force_start.elapsed().as_secs_f32() * 1000.0 - tree_ms;
+
+        let accretions = sinks::accrete(&mut self.particles, self.step, self.sim_time + config.dt);
+        if !accretions.is_empty() {
+            // the stored forces belong to the old masses and particles
+            self.forces_ready = false;
+            if let Some(log) = self.log.as_mut() {
+                if let Err(logging_err) = log.write_accretions(&accretions) {
+                    eprintln!("Error writing accretion log: {}", logging_err);
+                }
+            }
+            self.accretions.extend(accretions);
+        }
+
+        let emitted_start = self.particles.len();
+        for emitter in self.emitters.iter_mut() {
+            emitter.emit(
+                &mut self.particles,
+                &mut self.rng,
+                config.dt,
+                &mut self.next_index,
+            );
+        }
+        // the tree is already built from the end of step positions, new particles get
+        // their forces from it and everything else keeps the forces it has
+        if self.forces_ready {
+            for particle in self.particles[emitted_start..].iter_mut() {
+                calculate_force(
+                    particle,
+                    &self.qt,
+                    &end_config,
+                    &self.potentials,
+                    &self.fields,
+                );
+            }
+        }
+
+        if let Some(mut stats) = log_stats {
+            stats.tree_ms = tree_ms;
+            stats.force_ms = force_ms;
+            let log = self.log.as_mut().unwrap();
+            if let Err(logging_err) =
+                log.write(&stats, &self.diagnostics, &self.initial_diagnostics)
+            {
+                eprintln!("Error writing diagnostics log: {}", logging_err);
+            }
+        }
+        self.step += 1;
+        self.sim_time += config.dt;
+        self.scale_factor = end_scale_factor;
+    }
+
+    pub fn toggle_log(&mut self) {
+        match self.log.take() {
+            Some(log) => {
+                println!("Saved diagnostics log to {}", log.path().display());
+                if let Err(logging_err) = log.finish() {
+                    eprintln!("Error writing diagnostics log: {}", logging_err);
+                }
+            }
+            None => match DiagnosticsLog::create(self.config.seed) {
+                Ok(log) => {
+                    println!("Logging diagnostics to {}", log.path().display());
+                    self.log = Some(log);
+                }
+                Err(logging_err) => eprintln!("Error creating diagnostics log: {}", logging_err),
+            },
+        }
+    }
+
+    fn print_sinks(&self) {
+        let sinks: Vec<_> = self.particles.iter().filter_map(|p| p.sink()).collect();
+        if sinks.is_empty() {
+            return;
+        }
+        println!(
+            "  sinks: {}, accreted mass = {:.4} ({} particles)",
+            sinks.len(),
+            sinks.iter().map(|sink| sink.accreted_mass).sum::<f32>(),
+            self.accretions.len(),
+        );
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            step: self.step,
+            sim_time: self.sim_time,
+            scale_factor: self.scale_factor,
+            config: self.config,
+            particles: self.particles.clone(),
+        }
+    }
+
+    // the run continues with the config it was saved with
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        if snapshot.config != self.config {
+            println!("Switching to the snapshot config: {:?}", snapshot.config);
+        }
+        self.config = snapshot.config;
+        self.particles = snapshot.particles;
+        self.step = snapshot.step;
+        self.sim_time = snapshot.sim_time;
+        self.scale_factor = snapshot.scale_factor;
+        self.next_index = next_index(&self.particles);
+        self.forces_ready = false;
+        self.qt = create_quadtree(&self.particles, self.config.world_size());
+        self.initial_diagnostics = Diagnostics::compute(
+            &self.particles,
+            &self.qt,
+            &self.config.at_scale_factor(self.scale_factor),
+            &self.potentials,
+            &self.fields,
+        );
+        self.diagnostics = self.initial_diagnostics;
+        // snapshots don't keep the energy the run started with
+        println!("Energy drift is measured from the loaded snapshot from now on");
+    }
+}
+
+fn next_index(particles: &[Particle]) -> usize {
+    particles
+        .iter()
+        .map(|particle| particle.index + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+// exact solution of dv/dt = -2 H v over half a step
+fn hubble_drag(particles: &mut [Particle], hubble_rate: f32, dt: f32) {
+    let damping = (-hubble_rate * dt).exp();
+    particles.par_iter_mut().for_each(|particle| {
+        particle.vel *= damping;
+    });
+}
+
+fn print_expansion(config: &SimConfig, scale_factor: f32) {
+    if let Some(cosmology) = config.cosmology {
+        println!(
+            "  a = {:.4}, z = {:.3}, H = {:.4e}",
+            scale_factor,
+            Cosmology::redshift(scale_factor),
+            cosmology.hubble_rate(scale_factor),
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use crate::particle::{Particle, ParticleKind};
+use rayon::prelude::*;
+
+// a particle swallowed by a sink, indices are `Particle::index`
+#[derive(Clone, Copy, Debug)]
+pub struct Accretion {
+    pub step: u32,
+    pub time: f32,
+    pub sink: usize,
+    pub particle: usize,
+    pub mass: f32,
+    // mass of the sink after swallowing the particle
+    pub sink_mass: f32,
+}
+
+// merges every particle inside the accretion radius of a sink into the closest such sink
+// and removes it. Mass, momentum, charge and the center of mass are conserved, the kinetic
+// energy of the relative motion is lost. Sinks don't swallow each other.
+// There are only a few sinks, so every particle is checked against all of them
+// directly instead of walking the tree, which may be older than the positions
+pub fn accrete(particles: &mut Vec<Particle>, step: u32, time: f32) -> Vec<Accretion> {
+    let sinks: Vec<(usize, f32)> = particles
+        .iter()
+        .enumerate()
+        .filter_map(|(slot, particle)| Some((slot, particle.sink()?.accretion_radius)))
+        .collect();
+    if sinks.is_empty() {
+        return Vec::new();
+    }
+
+    let captures: Vec<(usize, usize)> = particles
+        .par_iter()
+        .enumerate()
+        .filter(|(_, particle)| particle.sink().is_none())
+        .filter_map(|(slot, particle)| {
+            sinks
+                .iter()
+                .map(|&(sink_slot, radius)| {
+                    let distance = (particles[sink_slot].pos - particle.pos).norm();
+                    (sink_slot, radius, distance)
+                })
+                .filter(|&(_, radius, distance)| distance < radius)
+                .min_by(|a, b| a.2.total_cmp(&b.2))
+                .map(|(sink_slot, _, _)| (slot, sink_slot))
+        })
+        .collect();
+
+    let mut swallowed = vec![false; particles.len()];
+    let mut accretions = Vec::with_capacity(captures.len());
+    for (slot, sink_slot) in captures {
+        let particle = particles[slot];
+        let sink = &mut particles[sink_slot];
+        let mass = sink.mass + particle.mass;
+        sink.pos = (sink.pos * sink.mass + particle.pos * particle.mass) / mass;
+        sink.vel = (sink.vel * sink.mass + particle.vel * particle.mass) / mass;
+        sink.mass = mass;
+        sink.charge += particle.charge;
+        if let ParticleKind::Sink(state) = &mut sink.kind {
+            state.accreted_mass += particle.mass;
+        }
+        swallowed[slot] = true;
+        accretions.push(Accretion {
+            step,
+            time,
+            sink: sink.index,
+            particle: particle.index,
+            mass: particle.mass,
+            sink_mass: mass,
+        });
+    }
+
+    let mut slots = swallowed.into_iter();
+    particles.retain(|_| !slots.next().unwrap());
+    accretions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector2;
+
+    fn particle(x: f32, y: f32, vx: f32, mass: f32, index: usize) -> Particle {
+        Particle::new(Vector2::new(x, y), Vector2::new(vx, 0.0), mass, 1.0, index)
+    }
+
+    fn totals(particles: &[Particle]) -> (f32, Vector2<f32>, Vector2<f32>, f32) {
+        let mass: f32 = particles.iter().map(|particle| particle.mass).sum();
+        let momentum = particles
+            .iter()
+            .map(|particle| particle.vel * particle.mass)
+            .sum::<Vector2<f32>>();
+        let center_of_mass = particles
+            .iter()
+            .map(|particle| particle.pos * particle.mass)
+            .sum::<Vector2<f32>>()
+            / mass;
+        let charge = particles.iter().map(|particle| particle.charge).sum();
+        (mass, momentum, center_of_mass, charge)
+    }
+
+    #[test]
+    fn accretion_conserves_mass_momentum_and_charge() {
+        let mut particles = vec![
+            particle(0.0, 0.0, 1.0, 10.0, 0)
+                .with_sink(2.0)
+                .with_charge(1.0),
+            particle(1.0, 0.5, -3.0, 2.0, 1).with_charge(-0.5),
+            particle(0.0, -1.5, 0.5, 1.0, 2).with_charge(2.0),
+            particle(5.0, 0.0, 0.0, 1.0, 3).with_charge(1.0),
+        ];
+        let before = totals(&particles);
+        let accretions = accrete(&mut particles, 0, 0.0);
+        let after = totals(&particles);
+
+        assert_eq!(accretions.len(), 2);
+        assert_eq!(particles.len(), 2);
+        assert!((after.0 - before.0).abs() < 1e-5);
+        assert!((after.1 - before.1).norm() < 1e-5);
+        assert!((after.2 - before.2).norm() < 1e-5);
+        assert!((after.3 - before.3).abs() < 1e-5);
+    }
+
+    #[test]
+    fn particle_goes_to_the_closest_sink() {
+        let mut particles = vec![
+            particle(0.0, 0.0, 0.0, 10.0, 0).with_sink(5.0),
+            particle(4.0, 0.0, 0.0, 10.0, 1).with_sink(5.0),
+            particle(2.5, 0.0, 0.0, 1.0, 2),
+        ];
+        let accretions = accrete(&mut particles, 0, 0.0);
+        assert_eq!(accretions.len(), 1);
+        assert_eq!(accretions[0].sink, 1);
+        assert_eq!(particles[0].mass, 10.0);
+        assert_eq!(particles[1].mass, 11.0);
+    }
+
+    #[test]
+    fn sinks_dont_swallow_each_other() {
+        let mut particles = vec![
+            particle(0.0, 0.0, 0.0, 10.0, 0).with_sink(5.0),
+            particle(1.0, 0.0, 0.0, 1.0, 1).with_sink(5.0),
+        ];
+        let accretions = accrete(&mut particles, 0, 0.0);
+        assert!(accretions.is_empty());
+        assert_eq!(particles.len(), 2);
+    }
+
+    #[test]
+    fn accreted_mass_is_recorded() {
+        let mut particles = vec![
+            particle(0.0, 0.0, 0.0, 10.0, 7).with_sink(2.0),
+            particle(1.0, 0.0, 0.0, 2.0, 8),
+            particle(9.0, 0.0, 0.0, 3.0, 9),
+        ];
+        let first = accrete(&mut particles, 4, 2.0);
+        particles[1].pos = Vector2::new(-1.0, 0.0);
+        let second = accrete(&mut particles, 5, 2.5);
+
+        assert_eq!(first.len(), 1);
+        assert_eq!(
+            (
+                first[0].step,
+                first[0].time,
+                first[0].sink,
+                first[0].particle
+            ),
+            (4, 2.0, 7, 8)
+        );
+        assert_eq!((first[0].mass, first[0].sink_mass), (2.0, 12.0));
+        assert_eq!(second.len(), 1);
+        assert_eq!((second[0].step, second[0].particle), (5, 9));
+        assert_eq!((second[0].mass, second[0].sink_mass), (3.0, 15.0));
+
+        assert_eq!(particles.len(), 1);
+        assert_eq!(particles[0].sink().unwrap().accreted_mass, 5.0);
+        assert_eq!(particles[0].mass, 15.0);
+    }
+}
@@ -0,0 +1,354 @@
+use crate::config::{Integrator, SimConfig};
+use crate::cosmology::Cosmology;
+use crate::particle::{Particle, ParticleKind, SinkState};
+use crate::sph::{EquationOfState, SphConfig};
+use nalgebra::Vector2;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"GPSNAP\0\0";
+pub const SNAPSHOT_VERSION: u32 = 7;
+
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub step: u32,
+    pub sim_time: f32,
+    pub scale_factor: f32,
+    pub config: SimConfig,
+    pub particles: Vec<Particle>,
+}
+
+// little-endian layout, version 7:
+// magic, version: u32, step: u32, sim_time: f32,
+// g, coulomb, softening, theta, dt: f32, integrator: u8, world_width, world_height: f32,
+// has seed: u8, seed: u64,
+// has cosmology: u8, hubble, omega_matter, omega_lambda, initial_redshift, scale_factor: f32,
+// equation of state: u8 (0 isothermal, 1 adiabatic), sound speed or gamma, alpha, beta: f32,
+// neighbours: u32, particles count: u64,
+// then for every particle pos.x, pos.y, vel.x, vel.y, mass, radius, charge: f32, index: u64,
+// kind: u8 (0 collisionless, 1 gas, 2 sink) and two f32 of its state, internal_energy and
+// smoothing_length of gas, accretion_radius and accreted_mass of sinks, zeros otherwise
+//
+// version 1 had no theta and integrator, they are loaded as defaults,
+// versions before 3 had no seed, versions before 4 had no cosmology,
+// versions before 5 had no coulomb and charges, all particles are neutral,
+// versions before 6 had no gas, versions before 7 had no sinks
+pub fn save_snapshot(path: &Path, snapshot: &Snapshot) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+    writer.write_all(&snapshot.step.to_le_bytes())?;
+    write_f32(&mut writer, snapshot.sim_time)?;
+
+    let config = &snapshot.config;
+    for value in [
+        config.g,
+        config.coulomb,
+        config.softening,
+        config.theta,
+        config.dt,
+    ] {
+        write_f32(&mut writer, value)?;
+    }
+    let integrator: u8 = match config.integrator {
+        Integrator::Euler => 0,
+        Integrator::Leapfrog => 1,
+    };
+    writer.write_all(&[integrator])?;
+    write_f32(&mut writer, config.world_width)?;
+    write_f32(&mut writer, config.world_height)?;
+    writer.write_all(&[config.seed.is_some() as u8])?;
+    writer.write_all(&config.seed.unwrap_or(0).to_le_bytes())?;
+    writer.write_all(&[config.cosmology.is_some() as u8])?;
+    let cosmology = config.cosmology.unwrap_or(Cosmology {
+        hubble: 0.0,
+        omega_matter: 0.0,
+        omega_lambda: 0.0,
+        initial_redshift: 0.0,
+    });
+    for value in [
+        cosmology.hubble,
+        cosmology.omega_matter,
+        cosmology.omega_lambda,
+        cosmology.initial_redshift,
+        snapshot.scale_factor,
+    ] {
+        write_f32(&mut writer, value)?;
+    }
+    let (equation_of_state, parameter) = match config.sph.equation_of_state {
+        EquationOfState::Isothermal { sound_speed } => (0, sound_speed),
+        EquationOfState::Adiabatic { gamma } => (1, gamma),
+    };
+    writer.write_all(&[equation_of_state])?;
+    for value in [parameter, config.sph.alpha, config.sph.beta] {
+        write_f32(&mut writer, value)?;
+    }
+    writer.write_all(&config.sph.neighbours.to_le_bytes())?;
+
+    writer.write_all(&(snapshot.particles.len() as u64).to_le_bytes())?;
+    for particle in &snapshot.particles {
+        for value in [
+            particle.pos.x,
+            particle.pos.y,
+            particle.vel.x,
+            particle.vel.y,
+            particle.mass,
+            particle.radius,
+            particle.charge,
+        ] {
+            write_f32(&mut writer, value)?;
+        }
+        writer.write_all(&(particle.index as u64).to_le_bytes())?;
+        let (kind, state) = match particle.kind {
+            ParticleKind::Collisionless => (0, [0.0, 0.0]),
+            ParticleKind::Gas(gas) => (1, [gas.internal_energy, gas.smoothing_length]),
+            ParticleKind::Sink(sink) => (2, [sink.accretion_radius, sink.accreted_mass]),
+        };
+        writer.write_all(&[kind])?;
+        write_f32(&mut writer, state[0])?;
+        write_f32(&mut writer, state[1])?;
+    }
+    writer.flush()
+}
+
+pub fn load_snapshot(path: &Path) -> io::Result<Snapshot> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data(format!(
+            "{} is not a snapshot file",
+            path.display()
+        )));
+    }
+    let version = read_u32(&mut reader)?;
+    if version == 0 || version > SNAPSHOT_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported snapshot version {} (expected at most {})",
+            version, SNAPSHOT_VERSION
+        )));
+    }
+
+    let step = read_u32(&mut reader)?;
+    let sim_time = read_f32(&mut reader)?;
+    let mut config = SimConfig {
+        g: read_f32(&mut reader)?,
+        ..SimConfig::default()
+    };
+    if version >= 5 {
+        config.coulomb = read_f32(&mut reader)?;
+    }
+    config.softening = read_f32(&mut reader)?;
+    if version >= 2 {
+        config.theta = read_f32(&mut reader)?;
+    }
+    config.dt = read_f32(&mut reader)?;
+    if version >= 2 {
+        let mut integrator = [0];
+        reader.read_exact(&mut integrator)?;
+        config.integrator = match integrator[0] {
+            0 => Integrator::Euler,
+            1 => Integrator::Leapfrog,
+            unknown => return Err(invalid_data(format!("unknown integrator id {}", unknown))),
+        };
+    }
+    config.world_width = read_f32(&mut reader)?;
+    config.world_height = read_f32(&mut reader)?;
+    if version >= 3 {
+        let mut has_seed = [0];
+        reader.read_exact(&mut has_seed)?;
+        let seed = read_u64(&mut reader)?;
+        config.seed = (has_seed[0] != 0).then_some(seed);
+    }
+    let mut scale_factor = 1.0;
+    if version >= 4 {
+        let mut has_cosmology = [0];
+        reader.read_exact(&mut has_cosmology)?;
+        let cosmology = Cosmology {
+            hubble: read_f32(&mut reader)?,
+            omega_matter: read_f32(&mut reader)?,
+            omega_lambda: read_f32(&mut reader)?,
+            initial_redshift: read_f32(&mut reader)?,
+        };
+        scale_factor = read_f32(&mut reader)?;
+        config.cosmology = (has_cosmology[0] != 0).then_some(cosmology);
+    }
+    if version >= 6 {
+        let mut equation_of_state = [0];
+        reader.read_exact(&mut equation_of_state)?;
+        let parameter = read_f32(&mut reader)?;
+        config.sph = SphConfig {
+            equation_of_state: match equation_of_state[0] {
+                0 => EquationOfState::Isothermal {
+                    sound_speed: parameter,
+                },
+                1 => EquationOfState::Adiabatic { gamma: parameter },
+                unknown => {
+                    return Err(invalid_data(format!(
+                        "unknown equation of state id {}",
+                        unknown
+                    )))
+                }
+            },
+            alpha: read_f32(&mut reader)?,
+            beta: read_f32(&mut reader)?,
+            neighbours: read_u32(&mut reader)?,
+        };
+    }
+
+    let particles_amount = read_u64(&mut reader)?;
+    let mut particles = Vec::new();
+    for _ in 0..particles_amount {
+        let pos = Vector2::new(read_f32(&mut reader)?, read_f32(&mut reader)?);
+        let vel = Vector2::new(read_f32(&mut reader)?, read_f32(&mut reader)?);
+        let mass = read_f32(&mut reader)?;
+        let radius = read_f32(&mut reader)?;
+        let charge = if version >= 5 {
+            read_f32(&mut reader)?
+        } else {
+            0.0
+        };
+        let index = read_u64(&mut reader)? as usize;
+        let mut particle = Particle::new(pos, vel, mass, radius, index).with_charge(charge);
+        if version >= 6 {
+            let mut kind = [0];
+            reader.read_exact(&mut kind)?;
+            let state = [read_f32(&mut reader)?, read_f32(&mut reader)?];
+            particle.kind = match kind[0] {
+                0 => ParticleKind::Collisionless,
+                1 => particle.with_gas(state[0], state[1]).kind,
+                2 => ParticleKind::Sink(SinkState {
+                    accretion_radius: state[0],
+                    accreted_mass: state[1],
+                }),
+                unknown => return Err(invalid_data(format!("unknown particle kind {}", unknown))),
+            };
+        }
+        particles.push(particle);
+    }
+
+    Ok(Snapshot {
+        step,
+        sim_time,
+        scale_factor,
+        config,
+        particles,
+    })
+}
+
+pub fn latest_snapshot(directory: &Path) -> Option<PathBuf> {
+    // timestamped names sort in chronological order
+    fs::read_dir(directory)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "snap")
+        })
+        .max()
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_f32(writer: &mut impl Write, value: f32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_round_trip_keeps_every_particle_kind() {
+        let config = SimConfig {
+            integrator: Integrator::Leapfrog,
+            seed: Some(42),
+            cosmology: Some(Cosmology {
+                hubble: 0.003,
+                omega_matter: 0.3,
+                omega_lambda: 0.7,
+                initial_redshift: 4.0,
+            }),
+            sph: SphConfig {
+                equation_of_state: EquationOfState::Isothermal { sound_speed: 0.2 },
+                ..SphConfig::default()
+            },
+            ..SimConfig::default()
+        };
+        let mut sink = Particle::new(Vector2::new(5.0, 6.0), Vector2::new(0.5, 0.0), 9.0, 2.0, 2)
+            .with_sink(3.0);
+        if let ParticleKind::Sink(state) = &mut sink.kind {
+            state.accreted_mass = 1.5;
+        }
+        let snapshot = Snapshot {
+            step: 17,
+            sim_time: 8.5,
+            scale_factor: 0.3,
+            config,
+            particles: vec![
+                Particle::new(
+                    Vector2::new(1.0, 2.0),
+                    Vector2::new(-1.0, 0.25),
+                    1.0,
+                    1.0,
+                    0,
+                )
+                .with_charge(-2.0),
+                Particle::new(Vector2::new(3.0, 4.0), Vector2::new(0.0, 1.0), 0.1, 0.5, 1)
+                    .with_gas(0.02, 1.5),
+                sink,
+            ],
+        };
+
+        let path = std::env::temp_dir().join(format!("{}_round_trip.snap", std::process::id()));
+        save_snapshot(&path, &snapshot).unwrap();
+        let loaded = load_snapshot(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.step, snapshot.step);
+        assert_eq!(loaded.sim_time, snapshot.sim_time);
+        assert_eq!(loaded.scale_factor, snapshot.scale_factor);
+        assert_eq!(loaded.config, snapshot.config);
+        assert_eq!(loaded.particles.len(), snapshot.particles.len());
+        for (loaded, saved) in loaded.particles.iter().zip(&snapshot.particles) {
+            assert_eq!(loaded.pos, saved.pos);
+            assert_eq!(loaded.vel, saved.vel);
+            assert_eq!(loaded.mass, saved.mass);
+            assert_eq!(loaded.radius, saved.radius);
+            assert_eq!(loaded.charge, saved.charge);
+            assert_eq!(loaded.index, saved.index);
+            assert_eq!(loaded.sink(), saved.sink());
+            assert_eq!(
+                loaded
+                    .gas()
+                    .map(|gas| (gas.internal_energy, gas.smoothing_length)),
+                saved
+                    .gas()
+                    .map(|gas| (gas.internal_energy, gas.smoothing_length))
+            );
+        }
+        assert!(loaded.particles[1].gas().is_some());
+        assert!(loaded.particles[2].sink().is_some());
+    }
+}
@@ -0,0 +1,121 @@
+use crate::diagnostics::Diagnostics;
+use crate::generators::seeded_rng;
+use crate::scenario::Scenario;
+use crate::simulation::Simulation;
+use crate::utils::create_quadtree;
+use nalgebra::Vector2;
+
+pub const SOLAR_SYSTEM_SCENARIO: &str = include_str!("../scenarios/solar_system.toml");
+
+// in AU. Alone with the Sun the Earth comes back within about 0.001 AU, the step error
+// and f32 rounding of positions around the middle of the 80 AU world. The other
+// planets pull it about 0.004 AU further off over the year, real physics and not an error
+pub const EARTH_TOLERANCE: f32 = 0.006;
+// relative to the total energy, leapfrog keeps it bounded on Keplerian orbits
+pub const ENERGY_TOLERANCE: f32 = 1e-4;
+// in solar masses times AU per year, the system starts at rest in its center of mass frame
+pub const MOMENTUM_TOLERANCE: f32 = 1e-6;
+
+pub struct YearReport {
+    pub steps: u32,
+    // distance of the Earth from where it started relative to the Sun, in AU
+    pub earth_error: f32,
+    pub energy_drift: f32,
+    pub momentum: f32,
+}
+
+fn heliocentric_pos(simulation: &Simulation, sun: usize, earth: usize) -> Vector2<f32> {
+    let pos = |index| {
+        simulation
+            .particles
+            .iter()
+            .find(|particle| particle.index == index)
+            .expect("the Sun and the Earth stay in the solar system")
+            .pos
+    };
+    pos(earth) - pos(sun)
+}
+
+// runs a solar system scenario with a "Sun" and an "Earth" for one year
+pub fn run_one_year(scenario: &str) -> YearReport {
+    let scenario = Scenario::parse(scenario).expect("Solar system scenario is invalid");
+    let config = scenario.config;
+    let (particles, names) = scenario
+        .build_named(&config, &mut seeded_rng(0))
+        .expect("Failed to build the solar system");
+    let body = |name: &str| {
+        *names
+            .get(name)
+            .unwrap_or_else(|| panic!("the solar system has no body named {}", name))
+    };
+    let (sun, earth) = (body("Sun"), body("Earth"));
+    let mut simulation = Simulation::new(particles, config, Vec::new(), Vec::new(), Vec::new());
+
+    let start = heliocentric_pos(&simulation, sun, earth);
+    let steps = (1.0 / config.dt).round() as u32;
+    for _ in 0..steps {
+        simulation.update();
+    }
+    let qt = create_quadtree(&simulation.particles, config.world_size());
+    let diagnostics = Diagnostics::compute(&simulation.particles, &qt, &config, &[], &[]);
+
+    YearReport {
+        steps,
+        earth_error: (heliocentric_pos(&simulation, sun, earth) - start).norm(),
+        energy_drift: diagnostics.energy_drift(&simulation.initial_diagnostics),
+        momentum: diagnostics.momentum.norm(),
+    }
+}
+
+// runs the bundled solar system for one year and checks the Earth comes back to
+// where it started relative to the Sun, while energy and momentum are conserved
+pub fn check_solar_system() -> bool {
+    let report = run_one_year(SOLAR_SYSTEM_SCENARIO);
+    let passed = report.earth_error <= EARTH_TOLERANCE
+        && report.energy_drift.abs() <= ENERGY_TOLERANCE
+        && report.momentum <= MOMENTUM_TOLERANCE;
+    println!(
+        "After one year ({} steps) the Earth is {:.5} AU away from its start (tolerance {} AU), energy drift {:.3e} (tolerance {:.0e}), |P| = {:.3e} (tolerance {:.0e}): {}",
+        report.steps,
+        report.earth_error,
+        EARTH_TOLERANCE,
+        report.energy_drift,
+        ENERGY_TOLERANCE,
+        report.momentum,
+        MOMENTUM_TOLERANCE,
+        if passed { "ok" } else { "FAILED" }
+    );
+    passed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one run for every check, a year of the solar system takes a while
+    #[test]
+    fn earth_returns_while_energy_and_momentum_are_conserved() {
+        // the README promises the same tolerance
+        assert!(include_str!("../README.md").contains(&format!(
+            "to within {} AU of its starting position",
+            EARTH_TOLERANCE
+        )));
+
+        let report = run_one_year(SOLAR_SYSTEM_SCENARIO);
+        assert!(
+            report.earth_error < EARTH_TOLERANCE,
+            "Earth off by {} AU",
+            report.earth_error
+        );
+        assert!(
+            report.energy_drift.abs() < ENERGY_TOLERANCE,
+            "energy drift {}",
+            report.energy_drift
+        );
+        assert!(
+            report.momentum < MOMENTUM_TOLERANCE,
+            "|P| = {}",
+            report.momentum
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use crate::config::SimConfig;
+use crate::particle::{Particle, ParticleKind};
+use crate::quadtree::QuadTree;
+use nalgebra::Vector2;
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+// e.g. `equation_of_state = { kind = "isothermal", sound_speed = 0.2 }` in `[config.sph]`
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum EquationOfState {
+    // P = c² ρ, the gas keeps its temperature and `internal_energy` doesn't change
+    Isothermal { sound_speed: f32 },
+    // P = (γ - 1) ρ u, compression and shocks heat the gas up
+    Adiabatic { gamma: f32 },
+}
+
+impl EquationOfState {
+    // pressure and sound speed of the gas
+    fn state(&self, density: f32, internal_energy: f32) -> (f32, f32) {
+        match *self {
+            Self::Isothermal { sound_speed } => (sound_speed.powi(2) * density, sound_speed),
+            Self::Adiabatic { gamma } => (
+                (gamma - 1.0) * density * internal_energy,
+                (gamma * (gamma - 1.0) * internal_energy).sqrt(),
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SphConfig {
+    pub equation_of_state: EquationOfState,
+    // Monaghan artificial viscosity, `alpha` damps the bulk flow and `beta` stops
+    // particles from streaming through each other in strong shocks
+    pub alpha: f32,
+    pub beta: f32,
+    // smoothing lengths adapt to keep about this many neighbours in the kernel
+    pub neighbours: u32,
+}
+
+impl Default for SphConfig {
+    fn default() -> Self {
+        Self {
+            equation_of_state: EquationOfState::Adiabatic { gamma: 5.0 / 3.0 },
+            alpha: 1.0,
+            beta: 2.0,
+            neighbours: 20,
+        }
+    }
+}
+
+impl SphConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.equation_of_state {
+            EquationOfState::Isothermal { sound_speed } => {
+                if !(sound_speed > 0.0 && sound_speed.is_finite()) {
+                    return Err(format!(
+                        "`sound_speed` has to be positive, got {}",
+                        sound_speed
+                    ));
+                }
+            }
+            EquationOfState::Adiabatic { gamma } => {
+                if !(gamma > 1.0 && gamma.is_finite()) {
+                    return Err(format!("`gamma` has to be more than 1, got {}", gamma));
+                }
+            }
+        }
+        for (field, value) in [("alpha", self.alpha), ("beta", self.beta)] {
+            if !(value >= 0.0 && value.is_finite()) {
+                return Err(format!("`{}` can't be negative, got {}", field, value));
+            }
+        }
+        if self.neighbours == 0 {
+            return Err("`neighbours` has to be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+// 2D cubic spline kernel, it reaches out to 2h
+fn kernel(r: f32, h: f32) -> f32 {
+    let q = r / h;
+    let sigma = 10.0 / (7.0 * PI * h * h);
+    if q < 1.0 {
+        sigma * (1.0 - 1.5 * q * q + 0.75 * q.powi(3))
+    } else if q < 2.0 {
+        sigma * 0.25 * (2.0 - q).powi(3)
+    } else {
+        0.0
+    }
+}
+
+// dW/dr of the same kernel
+fn kernel_derivative(r: f32, h: f32) -> f32 {
+    let q = r / h;
+    let sigma = 10.0 / (7.0 * PI * h * h);
+    if q < 1.0 {
+        sigma / h * (-3.0 * q + 2.25 * q * q)
+    } else if q < 2.0 {
+        sigma / h * -0.75 * (2.0 - q).powi(2)
+    } else {
+        0.0
+    }
+}
+
+// the part of a gas particle the force pass needs from its neighbours
+struct Fluid {
+    vel: Vector2<f32>,
+    density: f32,
+    pressure: f32,
+    sound_speed: f32,
+    // smoothing length for the next step
+    next_smoothing_length: f32,
+    neighbours: Vec<Particle>,
+}
+
+// density, pressure and the pressure and viscosity accelerations of every gas particle.
+// `qt` has to be built from the current positions, collisionless particles are skipped.
+// The accelerations are added to the net force by `calculate_force`
+pub fn update(particles: &mut [Particle], qt: &QuadTree, config: &SimConfig) {
+    let sph = &config.sph;
+    // the tree only has a copy of the particles from when it was built, velocities
+    // and the thermodynamic state of the neighbours are looked up by their index here
+    let fluids: HashMap<usize, Fluid> = particles
+        .par_iter()
+        .filter_map(|particle| {
+            let gas = particle.gas()?;
+            let h = gas.smoothing_length;
+            let mut neighbours = Vec::new();
+            qt.gas_neighbours(particle.pos, h, &mut neighbours);
+            neighbours.retain(|other| other.index != particle.index);
+
+            let mut density = particle.mass * kernel(0.0, h);
+            let mut count = 1;
+            for other in &neighbours {
+                let r = (other.pos - particle.pos).norm();
+                if r < 2.0 * h {
+                    density += other.mass * kernel(r, h);
+                    count += 1;
+                }
+            }
+            let (pressure, sound_speed) = sph.equation_of_state.state(density, gas.internal_energy);
+            // the neighbours in the kernel grow with its area, the change is limited
+            // so a single step can't blow the kernel up or collapse it
+            let ratio = (sph.neighbours as f32 / count as f32)
+                .sqrt()
+                .clamp(0.8, 1.25);
+            Some((
+                particle.index,
+                Fluid {
+                    vel: particle.vel,
+                    density,
+                    pressure,
+                    sound_speed,
+                    next_smoothing_length: h * ratio,
+                    neighbours,
+                },
+            ))
+        })
+        .collect();
+
+    particles.par_iter_mut().for_each(|particle| {
+        let (pos, vel) = (particle.pos, particle.vel);
+        let ParticleKind::Gas(gas) = &mut particle.kind else {
+            return;
+        };
+        let fluid = &fluids[&particle.index];
+        let h = gas.smoothing_length;
+        let pressure_term = fluid.pressure / fluid.density.powi(2);
+
+        let mut acceleration = Vector2::new(0.0, 0.0);
+        let mut energy_rate = 0.0;
+        for other in &fluid.neighbours {
+            let (Some(other_gas), Some(other_fluid)) = (other.gas(), fluids.get(&other.index))
+            else {
+                continue;
+            };
+            let offset = pos - other.pos;
+            let r = offset.norm();
+            let other_h = other_gas.smoothing_length;
+            if r == 0.0 || r >= 2.0 * h.max(other_h) {
+                continue;
+            }
+            // the average of both kernels keeps the pair forces symmetric
+            let gradient =
+                offset / r * (0.5 * (kernel_derivative(r, h) + kernel_derivative(r, other_h)));
+            let relative_vel = vel - other_fluid.vel;
+            let approach = relative_vel.dot(&offset);
+            let viscosity = if approach < 0.0 {
+                let mean_h = 0.5 * (h + other_h);
+                let mu = mean_h * approach / (r * r + 0.01 * mean_h * mean_h);
+                let mean_sound_speed = 0.5 * (fluid.sound_speed + other_fluid.sound_speed);
+                let mean_density = 0.5 * (fluid.density + other_fluid.density);
+                (-sph.alpha * mean_sound_speed * mu + sph.beta * mu * mu) / mean_density
+            } else {
+                0.0
+            };
+            let other_pressure_term = other_fluid.pressure / other_fluid.density.powi(2);
+            acceleration -=
+                gradient * (other.mass * (pressure_term + other_pressure_term + viscosity));
+            energy_rate +=
+                other.mass * (pressure_term + 0.5 * viscosity) * relative_vel.dot(&gradient);
+        }
+
+        gas.density = fluid.density;
+        gas.pressure = fluid.pressure;
+        gas.sound_speed = fluid.sound_speed;
+        gas.acceleration = acceleration;
+        gas.energy_rate = match sph.equation_of_state {
+            EquationOfState::Isothermal { .. } => 0.0,
+            EquationOfState::Adiabatic { .. } => energy_rate,
+        };
+        gas.smoothing_length = fluid.next_smoothing_length;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_quadtree;
+
+    #[test]
+    fn pair_forces_conserve_momentum() {
+        let config = SimConfig {
+            g: 0.0,
+            ..SimConfig::default()
+        };
+        // a squeezed, jittered lattice moving inwards, so viscosity takes part too
+        let mut particles: Vec<Particle> = (0..100)
+            .map(|i| {
+                let (column, row) = ((i % 10) as f32, (i / 10) as f32);
+                let jitter = Vector2::new((i as f32 * 1.7).sin(), (i as f32 * 2.3).cos()) * 0.3;
+                let offset = Vector2::new(column - 4.5, row - 4.5);
+                Particle::new(
+                    Vector2::new(256.0, 256.0) + offset + jitter,
+                    -offset * 0.05,
+                    0.01 * (1.0 + (i % 3) as f32),
+                    0.00001,
+                    i,
+                )
+                .with_gas(0.01 + 0.001 * (i % 7) as f32, 1.5 + 0.1 * (i % 4) as f32)
+            })
+            .collect();
+        let qt = create_quadtree(&particles, config.world_size());
+        update(&mut particles, &qt, &config);
+
+        let mut momentum_rate = Vector2::new(0.0, 0.0);
+        let mut scale = 0.0;
+        for particle in &particles {
+            let force = particle.gas().unwrap().acceleration * particle.mass;
+            momentum_rate += force;
+            scale += force.norm();
+        }
+        assert!(scale > 0.0);
+        assert!(
+            momentum_rate.norm() < scale * 1e-5,
+            "{} of {}",
+            momentum_rate.norm(),
+            scale
+        );
+    }
+}
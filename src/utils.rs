@@ -1,92 +1,77 @@
-use crate::consts::{
-    G, HEIGHT, LOWER_BOUND, MAX_ZOOM, UPPER_BOUND, WIDTH, WORLD_HEIGHT, WORLD_WIDTH,
-};
-use crate::particle::Particle;
+use crate::config::SimConfig;
+use crate::consts::MOUSE_AREA;
+use crate::forces::ForceField;
+use crate::particle::{Particle, ParticleKind};
+use crate::potentials::ExternalPotential;
 use crate::quadtree::QuadTree;
 use crate::rectangle::Rectangle;
 use chrono::{DateTime, Local};
 use ggez::graphics::{ImageEncodingFormat, ScreenImage};
 use ggez::Context;
 use nalgebra::Vector2;
-use rand::Rng;
 use std::fs;
 use std::io::{BufRead, BufReader};
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
 
-fn random_in_circle(radius: f32, padding: f32, center: Vector2<f32>) -> Vector2<f32> {
-    let mut rng = rand::thread_rng();
-    let angle = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
-    let distance = rng.gen_range(padding..radius);
-
-    Vector2::new(distance * angle.cos(), distance * angle.sin()) + center
-}
-
-pub fn spawn_circle(
-    particles: &mut Vec<Particle>,
-    center: Vector2<f32>,
-    radius: f32,
-    particle_mass: f32,
-    particles_amount: i32,
-) {
-    for i in 0..particles_amount {
-        let pos = random_in_circle(radius, 0.0, center);
-        let new_particle =
-            Particle::new(pos, Vector2::default(), particle_mass, 0.00001, i as usize);
-        particles.push(new_particle);
-    }
-}
-
-pub fn create_galaxy(
-    particles: &mut Vec<Particle>,
-    center: Vector2<f32>,
-    initial_vel: Vector2<f32>,
-    radius: f32,
-    sun_mass: f32,
-    particle_mass: f32,
-    particles_amount: i32,
-) {
-    for i in 0..particles_amount {
-        let pos = random_in_circle(radius, 2.0, center);
-        let distance_to_center = pos.metric_distance(&center);
-        let orbital_vel = ((G * sun_mass) / distance_to_center).sqrt();
-        let dir = Vector2::new(pos.y - center.y, center.x - pos.x).normalize();
-        let new_particle =
-            Particle::new(pos, dir * orbital_vel, particle_mass, 0.00001, i as usize);
-        particles.push(new_particle);
-    }
-
-    let sun = Particle::new(
-        center,
-        initial_vel,
-        sun_mass,
-        1.5,
-        particles_amount as usize,
-    );
-    particles.push(sun);
-}
-
-pub fn create_quadtree(particles: &Vec<Particle>) -> QuadTree {
+pub fn create_quadtree(particles: &[Particle], world_size: Vector2<f32>) -> QuadTree {
     let mut qt = QuadTree::new(Rectangle::new(
         Vector2::new(0.0, 0.0),
-        WORLD_WIDTH,
-        WORLD_HEIGHT,
+        world_size.x,
+        world_size.y,
     ));
-    for i in 0..particles.len() {
-        qt.insert(&particles[i]);
+    for particle in particles {
+        qt.insert(particle);
     }
     qt
 }
 
-pub fn calculate_new_position(particle: &mut Particle, qt: &mut QuadTree) {
+pub fn calculate_force(
+    particle: &mut Particle,
+    qt: &QuadTree,
+    config: &SimConfig,
+    potentials: &[Box<dyn ExternalPotential>],
+    fields: &[ForceField],
+) {
     particle.net_force = Vector2::new(0.0, 0.0);
-    qt.calculate_force(particle);
+    qt.calculate_force(particle, config);
+    for potential in potentials {
+        particle.net_force += potential.acceleration(particle.pos, config) * particle.mass;
+    }
+    // velocity dependent, leapfrog evaluates them with the half kicked velocity
+    for field in fields {
+        particle.net_force += field.acceleration(particle.pos, particle.vel) * particle.mass;
+    }
+    // computed for all the gas at once by `sph::update`
+    if let Some(gas) = particle.gas() {
+        particle.net_force += gas.acceleration * particle.mass;
+    }
     // println!("{:?}", borrowed.net_force);
+}
 
+pub fn kick(particle: &mut Particle, dt: f32) {
     let acceleration = particle.net_force / particle.mass;
-    particle.vel += acceleration;
+    particle.vel += acceleration * dt;
+    if let ParticleKind::Gas(gas) = &mut particle.kind {
+        gas.internal_energy = (gas.internal_energy + gas.energy_rate * dt).max(0.0);
+    }
+}
+
+pub fn drift(particle: &mut Particle, dt: f32) {
     let velocity = particle.vel;
-    particle.pos += velocity;
+    particle.pos += velocity * dt;
+}
+
+pub fn calculate_new_position(
+    particle: &mut Particle,
+    qt: &QuadTree,
+    config: &SimConfig,
+    potentials: &[Box<dyn ExternalPotential>],
+    fields: &[ForceField],
+) {
+    calculate_force(particle, qt, config, potentials, fields);
+    kick(particle, config.dt);
+    drift(particle, config.dt);
 }
 
 pub fn world_to_screen_coords(
@@ -130,10 +115,14 @@ pub fn rename_images(ctx: &Context) {
     }
 }
 
+pub fn timestamp() -> String {
+    let local: DateTime<Local> = Local::now();
+    local.format("%Y-%m-%d_%H.%M.%S").to_string()
+}
+
 pub fn convert_to_video(ctx: &Context) {
     let data_dir = ctx.fs.user_data_dir().to_string_lossy().to_string();
-    let local: DateTime<Local> = Local::now();
-    let formatted_date_time = local.format("%Y-%m-%d_%H.%M.%S").to_string();
+    let formatted_date_time = timestamp();
     let mut cmd = Command::new("ffmpeg")
         .args([
             "-framerate",
@@ -174,37 +163,51 @@ pub fn clean_cache_images(ctx: &Context) {
     }
 }
 
-pub fn move_on_mouse(ctx: &mut Context, origin: &mut Vector2<f32>, zoom: f32) {
+pub fn move_on_mouse(
+    ctx: &mut Context,
+    origin: &mut Vector2<f32>,
+    zoom: f32,
+    window_size: Vector2<f32>,
+    world_size: Vector2<f32>,
+) {
     const DESIRED_FPS: u32 = 60;
+    let lower_bound = Vector2::new(MOUSE_AREA, MOUSE_AREA);
+    let upper_bound = window_size - lower_bound;
 
     while ctx.time.check_update_time(DESIRED_FPS) {
         let mouse_position = ctx.mouse.position();
 
-        if mouse_position.x < LOWER_BOUND.x {
+        if mouse_position.x < lower_bound.x {
             origin.x += 5.0;
-        } else if mouse_position.x > UPPER_BOUND.x {
+        } else if mouse_position.x > upper_bound.x {
             origin.x -= 5.0;
         }
-        if mouse_position.y < LOWER_BOUND.y {
+        if mouse_position.y < lower_bound.y {
             origin.y += 5.0;
-        } else if mouse_position.y > UPPER_BOUND.y {
+        } else if mouse_position.y > upper_bound.y {
             origin.y -= 5.0;
         }
 
         if -origin.x < 0.0 {
             origin.x = 0.0;
-        } else if -origin.x + WIDTH / zoom > WORLD_WIDTH {
-            origin.x = WIDTH / zoom - WORLD_WIDTH;
+        } else if -origin.x + window_size.x / zoom > world_size.x {
+            origin.x = window_size.x / zoom - world_size.x;
         }
         if -origin.y < 0.0 {
             origin.y = 0.0;
-        } else if -origin.y + HEIGHT / zoom > WORLD_HEIGHT {
-            origin.y = HEIGHT / zoom - WORLD_HEIGHT;
+        } else if -origin.y + window_size.y / zoom > world_size.y {
+            origin.y = window_size.y / zoom - world_size.y;
         }
     }
 }
 
-pub fn zoom_world(ctx: &Context, origin: &mut Vector2<f32>, zoom: &mut f32, y_diff: f32) {
+pub fn zoom_world(
+    ctx: &Context,
+    origin: &mut Vector2<f32>,
+    zoom: &mut f32,
+    y_diff: f32,
+    max_zoom: f32,
+) {
     let mouse_x = ctx.mouse.position().x;
     let mouse_y = ctx.mouse.position().y;
 
@@ -214,8 +217,8 @@ pub fn zoom_world(ctx: &Context, origin: &mut Vector2<f32>, zoom: &mut f32, y_di
     } else if y_diff < 0.0 {
         *zoom /= 1.1;
     }
-    if *zoom < MAX_ZOOM {
-        *zoom = MAX_ZOOM;
+    if *zoom < max_zoom {
+        *zoom = max_zoom;
     }
     mouse_world = world_to_screen_coords(mouse_world, origin, *zoom);
 
@@ -0,0 +1,284 @@
+use crate::particle::Particle;
+use crate::utils::timestamp;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// VTK XML polydata, one vertex cell per particle so ParaView renders them as points
+pub fn write_vtp(path: &Path, particles: &[Particle]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let amount = particles.len();
+    writeln!(writer, "<?xml version=\"1.0\"?>")?;
+    writeln!(
+        writer,
+        "<VTKFile type=\"PolyData\" version=\"0.1\" byte_order=\"LittleEndian\">"
+    )?;
+    writeln!(writer, "  <PolyData>")?;
+    writeln!(
+        writer,
+        "    <Piece NumberOfPoints=\"{amount}\" NumberOfVerts=\"{amount}\" NumberOfLines=\"0\" NumberOfStrips=\"0\" NumberOfPolys=\"0\">"
+    )?;
+
+    writeln!(
+        writer,
+        "      <PointData Scalars=\"speed\" Vectors=\"velocity\">"
+    )?;
+    write_data_array(&mut writer, "Float32", "velocity", 3, particles, |p| {
+        format!("{} {} 0", p.vel.x, p.vel.y)
+    })?;
+    write_data_array(&mut writer, "Float32", "mass", 1, particles, |p| {
+        p.mass.to_string()
+    })?;
+    write_data_array(&mut writer, "Float32", "speed", 1, particles, |p| {
+        p.vel.norm().to_string()
+    })?;
+    write_data_array(&mut writer, "Float32", "radius", 1, particles, |p| {
+        p.radius.to_string()
+    })?;
+    write_data_array(&mut writer, "Float32", "charge", 1, particles, |p| {
+        p.charge.to_string()
+    })?;
+    // zero for collisionless particles
+    write_data_array(&mut writer, "Float32", "density", 1, particles, |p| {
+        p.gas().map_or(0.0, |gas| gas.density).to_string()
+    })?;
+    write_data_array(&mut writer, "Float32", "pressure", 1, particles, |p| {
+        p.gas().map_or(0.0, |gas| gas.pressure).to_string()
+    })?;
+    write_data_array(
+        &mut writer,
+        "Float32",
+        "internal_energy",
+        1,
+        particles,
+        |p| p.gas().map_or(0.0, |gas| gas.internal_energy).to_string(),
+    )?;
+    write_data_array(&mut writer, "Int64", "index", 1, particles, |p| {
+        p.index.to_string()
+    })?;
+    writeln!(writer, "      </PointData>")?;
+
+    writeln!(writer, "      <Points>")?;
+    write_data_array(&mut writer, "Float32", "position", 3, particles, |p| {
+        format!("{} {} 0", p.pos.x, p.pos.y)
+    })?;
+    writeln!(writer, "      </Points>")?;
+
+    writeln!(writer, "      <Verts>")?;
+    writeln!(
+        writer,
+        "        <DataArray type=\"Int64\" Name=\"connectivity\" format=\"ascii\">"
+    )?;
+    for i in 0..amount {
+        writeln!(writer, "          {}", i)?;
+    }
+    writeln!(writer, "        </DataArray>")?;
+    writeln!(
+        writer,
+        "        <DataArray type=\"Int64\" Name=\"offsets\" format=\"ascii\">"
+    )?;
+    for i in 0..amount {
+        writeln!(writer, "          {}", i + 1)?;
+    }
+    writeln!(writer, "        </DataArray>")?;
+    writeln!(writer, "      </Verts>")?;
+
+    writeln!(writer, "    </Piece>")?;
+    writeln!(writer, "  </PolyData>")?;
+    writeln!(writer, "</VTKFile>")?;
+    writer.flush()
+}
+
+fn write_data_array(
+    writer: &mut impl Write,
+    data_type: &str,
+    name: &str,
+    components: u32,
+    particles: &[Particle],
+    value: impl Fn(&Particle) -> String,
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "        <DataArray type=\"{data_type}\" Name=\"{name}\" NumberOfComponents=\"{components}\" format=\"ascii\">"
+    )?;
+    for particle in particles {
+        writeln!(writer, "          {}", value(particle))?;
+    }
+    writeln!(writer, "        </DataArray>")
+}
+
+pub fn write_ply(path: &Path, particles: &[Particle]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format ascii 1.0")?;
+    writeln!(writer, "comment gravitation-particles snapshot")?;
+    writeln!(writer, "element vertex {}", particles.len())?;
+    for property in [
+        "x", "y", "z", "vx", "vy", "vz", "mass", "speed", "radius", "charge",
+    ] {
+        writeln!(writer, "property float {}", property)?;
+    }
+    writeln!(writer, "property int index")?;
+    writeln!(writer, "end_header")?;
+    for p in particles {
+        writeln!(
+            writer,
+            "{} {} 0 {} {} 0 {} {} {} {} {}",
+            p.pos.x,
+            p.pos.y,
+            p.vel.x,
+            p.vel.y,
+            p.mass,
+            p.vel.norm(),
+            p.radius,
+            p.charge,
+            p.index
+        )?;
+    }
+    writer.flush()
+}
+
+// a folder with one .vtp (and a matching .ply) per exported frame and a .pvd time index
+// that ParaView opens as a single time series
+pub struct VtkSeries {
+    directory: PathBuf,
+    frames: Vec<(f32, String)>,
+}
+
+impl VtkSeries {
+    pub fn create() -> io::Result<Self> {
+        Self::create_in(PathBuf::from(format!("results/{}_vtk", timestamp())))
+    }
+
+    fn create_in(directory: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&directory)?;
+        Ok(Self {
+            directory,
+            frames: Vec::new(),
+        })
+    }
+
+    pub fn directory(&self) -> &PathBuf {
+        &self.directory
+    }
+
+    pub fn write_frame(&mut self, particles: &[Particle], sim_time: f32) -> io::Result<PathBuf> {
+        let name = format!("frame_{:06}", self.frames.len());
+        let vtp_name = format!("{}.vtp", name);
+        let vtp_path = self.directory.join(&vtp_name);
+        write_vtp(&vtp_path, particles)?;
+        write_ply(&self.directory.join(format!("{}.ply", name)), particles)?;
+        self.frames.push((sim_time, vtp_name));
+        // rewritten every time so the index stays valid if the run is interrupted
+        self.write_pvd()?;
+        Ok(vtp_path)
+    }
+
+    fn write_pvd(&self) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(self.directory.join("particles.pvd"))?);
+        writeln!(writer, "<?xml version=\"1.0\"?>")?;
+        writeln!(
+            writer,
+            "<VTKFile type=\"Collection\" version=\"0.1\" byte_order=\"LittleEndian\">"
+        )?;
+        writeln!(writer, "  <Collection>")?;
+        for (sim_time, file) in &self.frames {
+            writeln!(
+                writer,
+                "    <DataSet timestep=\"{}\" group=\"\" part=\"0\" file=\"{}\"/>",
+                sim_time, file
+            )?;
+        }
+        writeln!(writer, "  </Collection>")?;
+        writeln!(writer, "</VTKFile>")?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector2;
+
+    fn particles(amount: usize) -> Vec<Particle> {
+        (0..amount)
+            .map(|i| {
+                Particle::new(
+                    Vector2::new(i as f32, 2.0 * i as f32),
+                    Vector2::new(3.0, 4.0),
+                    0.5 + i as f32,
+                    1.0,
+                    i,
+                )
+            })
+            .collect()
+    }
+
+    // values of the named data array, one entry per line
+    fn data_array(vtp: &str, name: &str) -> Vec<String> {
+        let start = format!("Name=\"{name}\"");
+        vtp.lines()
+            .skip_while(|line| !line.contains(&start))
+            .skip(1)
+            .take_while(|line| !line.contains("</DataArray>"))
+            .map(|line| line.trim().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn frames_hold_every_particle_and_the_index_lists_every_frame() {
+        let directory = std::env::temp_dir().join(format!("{}_vtk", std::process::id()));
+        let mut series = VtkSeries::create_in(directory.clone()).unwrap();
+        let counts = [3, 5, 4];
+        for (i, &amount) in counts.iter().enumerate() {
+            series
+                .write_frame(&particles(amount), 0.5 * i as f32)
+                .unwrap();
+        }
+
+        for (i, &amount) in counts.iter().enumerate() {
+            let name = format!("frame_{:06}", i);
+            let vtp = fs::read_to_string(directory.join(format!("{name}.vtp"))).unwrap();
+            assert!(vtp.contains(&format!("NumberOfPoints=\"{amount}\"")));
+            assert_eq!(data_array(&vtp, "position").len(), amount);
+            assert_eq!(data_array(&vtp, "velocity"), vec!["3 4 0"; amount]);
+            assert_eq!(data_array(&vtp, "speed"), vec!["5"; amount]);
+            let masses: Vec<String> = (0..amount).map(|i| (0.5 + i as f32).to_string()).collect();
+            assert_eq!(data_array(&vtp, "mass"), masses);
+
+            let ply = fs::read_to_string(directory.join(format!("{name}.ply"))).unwrap();
+            let (header, body) = ply.split_once("end_header\n").unwrap();
+            assert!(header.contains(&format!("element vertex {amount}")));
+            let properties: Vec<&str> = header
+                .lines()
+                .filter_map(|line| line.strip_prefix("property "))
+                .filter_map(|property| property.split_whitespace().nth(1))
+                .collect();
+            let column = |name: &str| properties.iter().position(|&p| p == name).unwrap();
+            let rows: Vec<Vec<&str>> = body
+                .lines()
+                .map(|line| line.split_whitespace().collect())
+                .collect();
+            assert_eq!(rows.len(), amount);
+            for (row, mass) in rows.iter().zip(&masses) {
+                assert_eq!(row.len(), properties.len());
+                assert_eq!(row[column("vx")], "3");
+                assert_eq!(row[column("vy")], "4");
+                assert_eq!(row[column("speed")], "5");
+                assert_eq!(row[column("mass")], mass);
+            }
+        }
+
+        let pvd = fs::read_to_string(directory.join("particles.pvd")).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        let datasets: Vec<&str> = pvd
+            .lines()
+            .filter(|line| line.contains("<DataSet"))
+            .collect();
+        assert_eq!(datasets.len(), counts.len());
+        for (i, dataset) in datasets.iter().enumerate() {
+            assert!(dataset.contains(&format!("timestep=\"{}\"", 0.5 * i as f32)));
+            assert!(dataset.contains(&format!("file=\"frame_{:06}.vtp\"", i)));
+        }
+    }
+}